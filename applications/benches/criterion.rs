@@ -3,8 +3,8 @@ use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, Duration};
 use std::thread;
 use umi::endpoint::{UMIEndpoint, ResourceTable};
-use umi::{register, remote};
-use applications::reminder::ready_reminder_server_multi::{ReadyReminderServer, Entry};
+use umi::{remote};
+use applications::reminder::ready_reminder_server_multi::{ReadyReminderServer};
 use umi_macros_proc::{setup_packages, setup_registry, setup_proc_macros};
 setup_packages!();
 setup_registry!();
@@ -20,9 +20,7 @@ fn fib(n: u64) -> u64 {
 
 fn server_setup() -> (){
     let mut table = RegistryTable::new();
-    register!(table, ReadyReminderServerNew, ReadyReminderServer::new, fn() -> ReadyReminderServer, (ReadyReminderServer, ResultOp::Owned));
-    register!(table, ReadyReminderServerSubmit, ReadyReminderServer::submit_event, fn(&mut ReadyReminderServer, String, SystemTime), ((), ResultOp::Owned), ReadyReminderServer, String, SystemTime, &mut ReadyReminderServer, String, SystemTime);
-    register!(table, ReadyReminderServerExtract, ReadyReminderServer::extract_event, fn(&mut ReadyReminderServer) -> Option<Entry>, (Option<Entry>, ResultOp::Owned), ReadyReminderServer, &mut ReadyReminderServer);
+    register_all::<ReadyReminderServer>(&mut table);
 }
 
 fn fib_benchmark(c: &mut Criterion) {
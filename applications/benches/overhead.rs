@@ -1,10 +1,10 @@
 use std::hint::black_box;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc};
 use std::time::{Instant, Duration, SystemTime};
 use std::thread;
 use umi::endpoint::{UMIEndpoint, ResourceTable};
-use umi::{register, remote};
-use applications::reminder::ready_reminder_server_multi::{ReadyReminderServer, Entry};
+use umi::{remote};
+use applications::reminder::ready_reminder_server_multi::{ReadyReminderServer};
 use umi_macros_proc::{setup_packages, setup_registry, setup_proc_macros};
 setup_packages!();
 setup_registry!();
@@ -28,14 +28,12 @@ fn bench<F>(name: &str, mut f: F) where
     let duration = then.elapsed();
     println!("{},{},{}", name, duration.as_millis(),SAMPLES);
 }
-fn server_setup() -> (UMIEndpoint, RegistryTable, Arc<Mutex<ResourceTable>>){
+fn server_setup() -> (UMIEndpoint, RegistryTable, Arc<ResourceTable>){
     let mut table = RegistryTable::new();
-    register!(table, ReadyReminderServerNew, ReadyReminderServer::new, fn() -> ReadyReminderServer, (ReadyReminderServer, ResultOp::Owned));
-    register!(table, ReadyReminderServerSubmit, ReadyReminderServer::submit_event, fn(&mut ReadyReminderServer, String, SystemTime), ((), ResultOp::Owned), ReadyReminderServer, String, SystemTime, &mut ReadyReminderServer, String, SystemTime);
-    register!(table, ReadyReminderServerExtract, ReadyReminderServer::extract_event, fn(&mut ReadyReminderServer) -> Option<Entry>, (Option<Entry>, ResultOp::Owned), ReadyReminderServer, &mut ReadyReminderServer);
+    register_all::<ReadyReminderServer>(&mut table);
 
     let mut server = UMIEndpoint::new("127.0.0.1:3335");
-    let vtable = Arc::new(Mutex::new(ResourceTable::new()));
+    let vtable = Arc::new(ResourceTable::new());
     return (server, table, vtable)
 }
 
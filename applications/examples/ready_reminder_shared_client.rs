@@ -0,0 +1,14 @@
+use std::time::{SystemTime, Duration};
+use std::{thread};
+use umi::{attach};
+use applications::reminder::ready_reminder_server_multi::{ReadyReminderServer};
+use umi_macros_proc::{setup_packages};
+setup_packages!();
+
+fn main() {
+    // every client submits to and extracts from the same reminder server
+    let mut r = attach!("127.0.0.1:3335", "reminders", ReadyReminderServer);
+    r.submit_event(format!("Hello from {}!", std::process::id()), SystemTime::now() + Duration::new(1, 0));
+    thread::sleep(Duration::new(2, 0));
+    println!("The first event is: {:?}", r.extract_event());
+}
@@ -1,20 +1,15 @@
-use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
+use std::sync::{Arc};
 use umi::endpoint::{UMIEndpoint, ResourceTable};
-use umi::{register};
-use applications::reminder::ready_reminder_server_multi::{ReadyReminderServer, Entry};
-use umi_macros_proc::{setup_packages, setup_registry, setup_proc_macros};
-setup_packages!();
-setup_registry!();
-setup_proc_macros!();
+use umi::registry::{RegistryTable, register_all};
+use applications::reminder::ready_reminder_server_multi::{ReadyReminderServer};
 
 fn main() {
     let mut table = RegistryTable::new();
-    register!(table, ReadyReminderServerNew, ReadyReminderServer::new, fn() -> ReadyReminderServer, (ReadyReminderServer, ResultOp::Owned));
-    register!(table, ReadyReminderServerSubmit, ReadyReminderServer::submit_event, fn(&mut ReadyReminderServer, String, SystemTime), ((), ResultOp::Owned), ReadyReminderServer, String, SystemTime, &mut ReadyReminderServer, String, SystemTime);
-    register!(table, ReadyReminderServerExtract, ReadyReminderServer::extract_event, fn(&mut ReadyReminderServer) -> Option<Entry>, (Option<Entry>, ResultOp::Owned), ReadyReminderServer, &mut ReadyReminderServer);
+    register_all::<ReadyReminderServer>(&mut table);
 
     let mut server = UMIEndpoint::new("127.0.0.1:3335");
-    let vtable = Arc::new(Mutex::new(ResourceTable::new()));
+    let vtable = Arc::new(ResourceTable::new());
+    // one reminder server shared by all the clients, see ready_reminder_shared_client
+    vtable.insert_named("reminders", Box::new(ReadyReminderServer::new())).unwrap();
     server.start(table, vtable);
-}
\ No newline at end of file
+}
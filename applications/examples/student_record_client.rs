@@ -8,4 +8,8 @@ fn main() {
     record.add_student("Jane Doe".to_string());
     println!("Has student John Doe? : {:?}", record.has_student("John Doe".to_string()));
     println!("Has student Jane Doe? : {:?}", record.has_student("Jane Doe".to_string()));
+    match record.is_empty() {
+        Ok(empty) => println!("Is the record empty? : {:?}", empty),
+        Err(e) => println!("Unable to ask the record: {}", e)
+    }
 }
\ No newline at end of file
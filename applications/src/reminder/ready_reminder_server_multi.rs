@@ -2,7 +2,7 @@ use std::time::SystemTime;
 use std::collections::BinaryHeap;
 use serde::{Serialize, Deserialize};
 
-use umi_macros_proc::{proxy_me, umi_init, umi_struct_method, setup_packages, setup_registry, setup_proc_macros};
+use umi_macros_proc::{proxy_me, umi_init, umi_struct_method, umi_register, setup_packages, setup_registry, setup_proc_macros};
 setup_packages!();
 setup_registry!();
 setup_proc_macros!();
@@ -67,6 +67,7 @@ pub struct ReadyReminderServer {
     entries: BinaryHeap<Entry>
 }
 
+#[umi_register]
 impl ReadyReminderServer {
     #[umi_init]
     pub fn new() -> ReadyReminderServer {
@@ -78,16 +79,16 @@ impl ReadyReminderServer {
     #[umi_struct_method]
     pub fn submit_event(&mut self, content: String, ready_at: SystemTime) {
         let entry = Entry::new(content, ready_at);
-        (&mut self.entries).push(entry);
+        self.entries.push(entry);
     }
 
     #[umi_struct_method(false)]
     pub fn extract_event(&mut self) -> Option<Entry> {
-        let first = (&self.entries).peek();
+        let first = self.entries.peek();
         match first {
             Some(entry) => {
                 if entry.get_time() <= &SystemTime::now() {
-                    let e = (&mut self.entries).pop();
+                    let e = self.entries.pop();
                     return e;
                 } else {
                     return None;
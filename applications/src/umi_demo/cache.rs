@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use umi_macros_proc::{proxy_me, umi_init, umi_struct_method, umi_register, setup_packages, setup_registry, setup_proc_macros};
+
+setup_packages!();
+setup_registry!();
+setup_proc_macros!();
+
+#[proxy_me]
+pub struct Cache<K, V> where K: Eq + Hash {
+    entries: HashMap<K, V>
+}
+
+#[umi_register(Cache<String, u32>)]
+// the proxy methods get the bounds they need to send the arguments and to decode the results
+impl<K, V> Cache<K, V> where K: Eq + Hash, V: Clone {
+    #[umi_init]
+    pub fn new() -> Self {
+        Cache {
+            entries: HashMap::new()
+        }
+    }
+
+    #[umi_struct_method]
+    pub fn insert(&mut self, key: K, value: V) {
+        self.entries.insert(key, value);
+    }
+
+    #[umi_struct_method(false)]
+    pub fn get(&self, key: K) -> Option<V> {
+        self.entries.get(&key).cloned()
+    }
+
+    // registered for each type of key listed
+    #[umi_struct_method(false, <String>)]
+    pub fn contains<Q>(&self, key: Q) -> bool where Q: Into<K> {
+        self.entries.contains_key(&key.into())
+    }
+}
+
+impl<K, V> Default for Cache<K, V> where K: Eq + Hash, V: Clone {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::umi_demo::serve;
+    use umi::remote;
+
+    #[test]
+    fn cache_works() {
+        let mut cache: Cache<String, u32> = Cache::new();
+        cache.insert("one".to_string(), 1);
+        assert_eq!(cache.get("one".to_string()), Some(1));
+        assert!(!cache.contains("two".to_string()));
+    }
+
+    #[test]
+    fn instantiations_are_registered_on_their_own() {
+        let mut table = RegistryTable::new();
+        register_all::<Cache<String, u32>>(&mut table);
+        let (addr, _) = serve(table);
+
+        let mut cache = remote!(addr, Cache::<String, u32>::new, Cache<String, u32>);
+        cache.insert("one".to_string(), 1);
+        assert_eq!(cache.get("one".to_string()), Some(1));
+        assert!(cache.contains("one".to_string()));
+        assert!(!cache.contains("two".to_string()));
+    }
+}
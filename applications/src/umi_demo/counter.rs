@@ -0,0 +1,162 @@
+use umi_macros_proc::{proxy_me, umi_init, umi_struct_method, umi_enum_method, umi_register, setup_packages, setup_registry, setup_proc_macros};
+
+setup_packages!();
+setup_registry!();
+setup_proc_macros!();
+
+#[proxy_me]
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Counter(u32);
+
+#[umi_register]
+impl Counter {
+    #[umi_init]
+    pub fn new() -> Self {
+        Counter(0)
+    }
+
+    #[umi_struct_method]
+    pub fn bump(&mut self) {
+        self.0 += 1;
+    }
+
+    #[umi_struct_method(false)]
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[proxy_me]
+pub struct Pinger;
+
+#[umi_register]
+impl Pinger {
+    #[umi_init]
+    pub fn new() -> Self {
+        Pinger
+    }
+
+    #[umi_struct_method(false)]
+    pub fn ping(&self) -> bool {
+        true
+    }
+}
+
+impl Default for Pinger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[proxy_me]
+#[derive(Debug)]
+pub enum Switch {
+    On,
+    Off
+}
+
+#[umi_register]
+impl Switch {
+    #[umi_init]
+    pub fn new() -> Self {
+        Switch::Off
+    }
+
+    #[umi_enum_method(false)]
+    pub fn is_on(&self) -> bool {
+        match self {
+            Switch::On => true,
+            _ => false
+        }
+    }
+
+    #[umi_enum_method(false, fallible)]
+    pub fn toggle(&mut self) -> bool {
+        match self {
+            Switch::On => {
+                *self = Switch::Off;
+                false
+            },
+            _ => {
+                *self = Switch::On;
+                true
+            }
+        }
+    }
+}
+
+impl Default for Switch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::umi_demo::serve;
+    use std::collections::HashSet;
+    use umi::{remote, remote_named};
+
+    #[test]
+    fn counter_works() {
+        let mut counter = Counter::new();
+        counter.bump();
+        assert_eq!(counter.value(), 1);
+        assert_eq!(format!("{:?}", counter), "Counter(1)");
+        assert!(Counter::new() < counter);
+        assert!(Pinger::new().ping());
+    }
+
+    #[test]
+    fn tuple_and_unit_structs_are_proxied() {
+        let mut table = RegistryTable::new();
+        register_all::<Counter>(&mut table);
+        register_all::<Pinger>(&mut table);
+        let (addr, vtable) = serve(table);
+
+        let mut counter = remote!(&addr, Counter::new, Counter);
+        counter.bump();
+        counter.bump();
+        assert_eq!(counter.value(), 2);
+        let pinger = remote!(&addr, Pinger::new, Pinger);
+        assert!(pinger.ping());
+        assert_eq!(vtable.len(), 2);
+
+        // proxies are equal when they stand for the same resource
+        let other = remote!(&addr, Counter::new, Counter);
+        assert_eq!(counter, counter.clone());
+        assert_ne!(counter, other);
+        assert_ne!(counter, CounterProxy::from(Counter::new()));
+        assert!(CounterProxy::from(Counter::new()) < counter);
+        let counters: HashSet<CounterProxy> = [counter.clone(), counter.clone(), other.clone()].into_iter().collect();
+        assert_eq!(counters.len(), 2);
+        assert!(format!("{:?}", counter).starts_with("CounterProxy::Remote("));
+    }
+
+    #[test]
+    fn enum_methods_return_errors_when_fallible() {
+        let mut switch = Switch::new();
+        assert!(!switch.is_on());
+        assert_eq!(switch.toggle(), Ok(true));
+        assert!(switch.is_on());
+
+        let mut table = RegistryTable::new();
+        register_all::<Switch>(&mut table);
+        let (addr, vtable) = serve(table);
+
+        let mut switch = remote_named!(&addr, "hall", Switch::new, Switch);
+        assert!(!switch.is_on());
+        assert_eq!(switch.toggle(), Ok(true));
+        assert!(switch.is_on());
+        assert!(matches!(switch, Switch::Remote(..)));
+        vtable.unbind("hall");
+        assert!(matches!(switch.toggle(), Err(UmiError::Remote(ErrorCode::UnknownResource, _))));
+    }
+}
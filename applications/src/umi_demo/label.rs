@@ -0,0 +1,68 @@
+use std::borrow::Cow;
+use umi_macros_proc::{proxy_me, umi_init, umi_struct_method, umi_register, setup_packages, setup_registry, setup_proc_macros};
+
+setup_packages!();
+setup_registry!();
+setup_proc_macros!();
+
+// borrows its text where it can, the resource on an endpoint owns it
+#[proxy_me]
+#[derive(Debug, PartialEq)]
+pub struct Label<'a> {
+    #[serde(borrow)]
+    text: Cow<'a, str>
+}
+
+#[umi_register(Label<'static>)]
+impl<'a> Label<'a> {
+    #[umi_init]
+    pub fn new(text: String) -> Self {
+        Label {
+            text: Cow::Owned(text)
+        }
+    }
+
+    pub fn borrowed(text: &'a str) -> Self {
+        Label {
+            text: Cow::Borrowed(text)
+        }
+    }
+
+    #[umi_struct_method(false)]
+    pub fn text(&self) -> String {
+        self.text.to_string()
+    }
+
+    #[umi_struct_method]
+    pub fn append(&mut self, suffix: String) {
+        self.text.to_mut().push_str(&suffix);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::umi_demo::serve;
+    use umi::remote;
+
+    #[test]
+    fn labels_borrow_their_text() {
+        let text = String::from("kitchen");
+        assert!(matches!(Label::borrowed(&text).text, Cow::Borrowed(_)));
+        let mut label = LabelProxy::from(Label::borrowed(&text));
+        assert_eq!(label.text(), "kitchen");
+        label.append(" shelf".to_string());
+        assert_eq!(label.text(), "kitchen shelf");
+    }
+
+    #[test]
+    fn resources_own_their_text() {
+        let mut table = RegistryTable::new();
+        register_all::<Label<'static>>(&mut table);
+        let (addr, _) = serve(table);
+
+        let mut label = remote!(addr, Label::<'static>::new, Label<'static>, "kitchen".to_string());
+        label.append(" shelf".to_string());
+        assert_eq!(label.text(), "kitchen shelf");
+    }
+}
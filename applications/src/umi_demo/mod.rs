@@ -1,2 +1,16 @@
+pub mod cache;
+pub mod counter;
+pub mod label;
 pub mod students;
-pub mod students_single;
\ No newline at end of file
+pub mod students_single;
+
+// starts an endpoint on a free local port, returns its address and the table of the resources it holds
+#[cfg(test)]
+fn serve(table: umi::registry::RegistryTable) -> (umi_macros::Address, std::sync::Arc<umi::endpoint::ResourceTable>) {
+    let mut server = umi::endpoint::UMIEndpoint::new("127.0.0.1:0");
+    let addr = server.local_address();
+    let vtable = std::sync::Arc::new(umi::endpoint::ResourceTable::new());
+    let resources = std::sync::Arc::clone(&vtable);
+    std::thread::spawn(move || server.start(table, resources));
+    (addr, vtable)
+}
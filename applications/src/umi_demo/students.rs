@@ -1,4 +1,4 @@
-use umi_macros_proc::{proxy_me, umi_init, umi_struct_method, setup_packages, setup_registry, setup_proc_macros};
+use umi_macros_proc::{proxy_me, umi_init, umi_struct_method, umi_register, setup_packages, setup_registry, setup_proc_macros};
 
 setup_packages!();
 setup_registry!();
@@ -7,11 +7,15 @@ setup_proc_macros!();
 
 pub type Student = String;
 
+/// The students enrolled in a course
 #[proxy_me]
+#[derive(Debug, PartialEq, Default)]
 pub struct StudentRecord {
+    /// Students in the order they were added
     students: Vec<Student>
 }
 
+#[umi_register]
 impl StudentRecord {
     #[umi_init]
     pub fn new() -> Self {
@@ -20,24 +24,271 @@ impl StudentRecord {
         }
     }
 
+    #[umi_init]
+    pub fn with_students(count: usize) -> Self {
+        let mut record = Self {
+            students: Vec::with_capacity(count)
+        };
+        record.students.extend((1..=count).map(|i| format!("Student {}", i)));
+        record
+    }
+
     #[umi_struct_method]
     pub fn add_student(&mut self, student: Student) {
-        (&mut self.students).push(student);
+        self.students.push(student);
     }
 
     #[umi_struct_method(false)]
     pub fn has_student(&self, student: Student) -> bool {
-        (&self.students).contains(&student)
+        self.students.contains(&student)
+    }
+
+    #[umi_struct_method(false, fallible)]
+    pub fn is_empty(&self) -> bool {
+        self.students.is_empty()
+    }
+
+    #[umi_struct_method(false)]
+    pub fn student(&self, index: usize) -> Student {
+        self.students[index].clone()
+    }
+
+    #[umi_struct_method]
+    pub fn rename_student(&mut self, index: usize, student: Student) {
+        self.students[index] = student;
+    }
+
+    #[umi_struct_method(false)]
+    pub fn remove_student(&mut self, student: Student) -> bool {
+        if !self.has_student(student.clone()) {
+            return false;
+        }
+        let index = self.position(&student);
+        self.students.remove(index);
+        true
+    }
+
+    // not registered, only called by the methods above
+    fn position(&self, student: &Student) -> usize {
+        self.students.iter().position(|s| s == student).unwrap()
+    }
+}
+
+#[proxy_me]
+pub struct Course {
+    record: StudentRecord,
+    seats: usize
+}
+
+#[umi_register]
+impl Course {
+    #[umi_init]
+    pub fn new() -> Self {
+        Course {
+            record: StudentRecord::new(),
+            seats: 0
+        }
+    }
+
+    // fails when the course has no seat
+    #[umi_init]
+    pub fn with_seats(seats: usize) -> Result<Self, String> {
+        if seats == 0 {
+            return Err("a course needs at least one seat".to_string());
+        }
+        Ok(Self {
+            record: StudentRecord { students: Vec::with_capacity(seats) },
+            seats
+        })
+    }
+
+    #[umi_struct_method]
+    pub fn open_seats(&mut self, seats: usize) {
+        self.seats += seats;
+    }
+
+    #[umi_struct_method(false)]
+    pub fn free_seats(&self) -> usize {
+        self.seats - self.record.students.len()
+    }
+
+    // enrolls a student of another record, the record is dropped afterwards
+    #[umi_struct_method(false)]
+    pub fn enroll_from(&mut self, record: StudentRecordProxy, student: Student) -> bool {
+        if !record.has_student(student.clone()) {
+            return false;
+        }
+        self.record.add_student(student);
+        true
+    }
+
+    #[umi_struct_method]
+    pub fn record(&self) -> &StudentRecord {
+        &self.record
+    }
+
+    #[umi_struct_method]
+    pub fn record_mut(&mut self) -> &mut StudentRecord {
+        &mut self.record
+    }
+}
+
+impl Default for Course {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::umi_demo::serve;
+    use umi::{remote, try_remote, remote_named, attach};
+    use umi::naming::names;
+
     #[test]
     fn student_record_works() {
         let mut record = StudentRecord::new();
         record.add_student("Jane".to_string());
         assert!(record.has_student("Jane".to_string()));
+        assert!(!record.is_empty());
+        // the proxy of a local value calls the methods of the value
+        let proxy = StudentRecordProxy::from(record);
+        assert!(proxy.has_student("Jane".to_string()));
+        assert_eq!(proxy.is_empty(), Ok(false));
+    }
+
+    #[test]
+    fn records_keep_their_derives() {
+        let mut record = StudentRecord::default();
+        assert_eq!(record, StudentRecord::new());
+        record.add_student("Jane".to_string());
+        assert_eq!(format!("{:?}", record), r#"StudentRecord { students: ["Jane"] }"#);
+        assert_ne!(record, StudentRecord::new());
+    }
+
+    #[test]
+    fn fallible_method_reports_unreachable_endpoint() {
+        let addr: Address = "127.0.0.1:1".parse().unwrap();
+        let record = StudentRecordProxy::Remote(Node::new([0; 16], vec![addr]), (std::time::SystemTime::now(), 0, umi_macros::Token([0; 16])), Arc::new(AtomicBool::new(false)));
+        assert!(matches!(record.is_empty(), Err(UmiError::Connect(_))));
+    }
+
+    #[test]
+    fn course_lends_its_record() {
+        let mut course = Course::new();
+        course.record_mut().add_student("Jane".to_string());
+        assert!(course.record().has_student("Jane".to_string()));
+        let mut course = CourseProxy::from(course);
+        course.record_mut().add_student("John".to_string());
+        assert!(course.record().has_student("John".to_string()));
+    }
+
+    #[test]
+    fn methods_use_fields_as_written() {
+        let mut table = RegistryTable::new();
+        register_all::<Course>(&mut table);
+        register_all::<StudentRecord>(&mut table);
+        let (addr, _) = serve(table);
+
+        let mut course = remote!(addr, Course::new, Course);
+        course.open_seats(2);
+        course.open_seats(1);
+        course.record_mut().add_student("Jane".to_string());
+        assert_eq!(course.free_seats(), 2);
+        let mut record = course.record_mut();
+        record.rename_student(0, "John".to_string());
+        assert_eq!(record.student(0), "John".to_string());
+    }
+
+    #[test]
+    fn methods_call_other_methods_on_the_endpoint() {
+        let mut table = RegistryTable::new();
+        register_all::<StudentRecord>(&mut table);
+        let (addr, _) = serve(table);
+
+        let mut record = remote!(addr, StudentRecord::new, StudentRecord);
+        record.add_student("Jane".to_string());
+        record.add_student("John".to_string());
+        assert!(record.remove_student("Jane".to_string()));
+        assert!(!record.remove_student("Jane".to_string()));
+        assert_eq!(record.student(0), "John".to_string());
+    }
+
+    #[test]
+    fn constructors_take_arguments() {
+        let mut table = RegistryTable::new();
+        register_all::<Course>(&mut table);
+        register_all::<StudentRecord>(&mut table);
+        let (addr, vtable) = serve(table);
+
+        let record = remote!(&addr, StudentRecord::with_students, StudentRecord, 2usize);
+        assert_eq!(record.student(1), "Student 2".to_string());
+        let course = remote!(&addr, Course::with_seats, Course, 3usize);
+        assert_eq!(course.free_seats(), 3);
+        let failed = try_remote!(&addr, Course::with_seats, Course, 0usize);
+        assert!(matches!(failed, Err(UmiError::Remote(ErrorCode::InitFailed, _))));
+        assert_eq!(vtable.len(), 2);
+    }
+
+    #[test]
+    fn moved_proxies_own_their_resource() {
+        let mut table = RegistryTable::new();
+        register_all::<StudentRecord>(&mut table);
+        let (records_addr, record_table) = serve(table);
+        let mut table = RegistryTable::new();
+        register_all::<Course>(&mut table);
+        register_all::<StudentRecord>(&mut table);
+        let (courses_addr, _) = serve(table);
+
+        let mut record = remote!(&records_addr, StudentRecord::new, StudentRecord);
+        record.add_student("Jane".to_string());
+        let mut course = remote!(&courses_addr, Course::new, Course);
+        assert!(course.enroll_from(record, "Jane".to_string()));
+        assert!(course.record().has_student("Jane".to_string()));
+        // the course endpoint dropped the record it was given
+        assert!(record_table.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn remote_borrows_are_released_by_their_guards() {
+        let mut table = RegistryTable::new();
+        register_all::<Course>(&mut table);
+        register_all::<StudentRecord>(&mut table);
+        let (addr, vtable) = serve(table);
+
+        let mut course = remote!(addr, Course::new, Course);
+        {
+            let mut record = course.record_mut();
+            record.add_student("Jane".to_string());
+            assert_eq!(vtable.len(), 2);
+        }
+        assert_eq!(vtable.len(), 1);
+        assert!(course.record().has_student("Jane".to_string()));
+        assert_eq!(vtable.len(), 1);
+        drop(course);
+        assert!(vtable.is_empty());
+    }
+
+    #[test]
+    fn clients_share_a_named_record() {
+        let mut table = RegistryTable::new();
+        register_all::<StudentRecord>(&mut table);
+        let (addr, vtable) = serve(table);
+        vtable.insert_named("bound in main", Box::new(StudentRecord::new())).unwrap();
+
+        let mut created = remote_named!(&addr, "created by a client", StudentRecord::new, StudentRecord);
+        created.add_student("Jane".to_string());
+        let mut attached = attach!(&addr, "created by a client", StudentRecord);
+        assert!(attached.has_student("Jane".to_string()));
+        attached.add_student("John".to_string());
+        drop(created);
+        assert!(attached.has_student("John".to_string()));
+
+        let mut other = attach!(&addr, "bound in main", StudentRecord);
+        other.add_student("Jane".to_string());
+        assert_eq!(names(&addr).unwrap(), vec!["bound in main".to_string(), "created by a client".to_string()]);
+        assert!(vtable.unbind("created by a client").is_some());
+        assert_eq!(vtable.len(), 1);
+    }
+}
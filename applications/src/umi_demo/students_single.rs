@@ -1,31 +1,59 @@
+use umi_macros_proc::{proxy_me, umi_init, umi_struct_method, umi_register, setup_packages, setup_registry, setup_proc_macros};
+
+setup_packages!();
+setup_registry!();
+setup_proc_macros!();
+
 pub type Student = String;
 
+#[proxy_me]
 pub struct StudentRecord {
     students: Vec<Student>
 }
 
+#[umi_register]
 impl StudentRecord {
+    #[umi_init]
     pub fn new() -> Self {
         StudentRecord {
             students: Vec::new()
         }
     }
+
+    #[umi_struct_method]
     pub fn add_student(&mut self, student: Student) {
-        (&mut self.students).push(student);
+        self.students.push(student);
     }
 
+    #[umi_struct_method(false)]
     pub fn has_student(&self, student: Student) -> bool {
-        (&self.students).contains(&student)
+        self.students.contains(&student)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::umi_demo::serve;
+    use umi::{remote};
+
     #[test]
     fn student_single_record_works() {
         let mut record = StudentRecord::new();
         record.add_student("Jane".to_string());
         assert!(record.has_student("Jane".to_string()));
+        assert_eq!(record.students, vec!["Jane".to_string()]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn student_single_record_is_distributed() {
+        let mut table = RegistryTable::new();
+        register_all::<StudentRecord>(&mut table);
+        let (addr, _) = serve(table);
+
+        let mut record = remote!(addr, StudentRecord::new, StudentRecord);
+        record.add_student("Jane".to_string());
+        assert!(record.has_student("Jane".to_string()));
+        assert!(!record.has_student("John".to_string()));
+    }
+}
@@ -0,0 +1,167 @@
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/* A serialization format for messages and the values they carry.
+ * The ID of the codec travels in the flags of every frame so that the endpoint answers in kind.
+ */
+pub trait Codec {
+    const ID: u8;
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError>;
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecError(pub String);
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "codec error: {}", self.0)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+pub struct Json;
+pub struct Bincode;
+pub struct MessagePack;
+
+impl Codec for Json {
+    const ID: u8 = 0;
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(value).map_err(|e| CodecError(e.to_string()))
+    }
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(bytes).map_err(|e| CodecError(e.to_string()))
+    }
+}
+
+impl Codec for Bincode {
+    const ID: u8 = 1;
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        bincode::serialize(value).map_err(|e| CodecError(e.to_string()))
+    }
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        bincode::deserialize(bytes).map_err(|e| CodecError(e.to_string()))
+    }
+}
+
+impl Codec for MessagePack {
+    const ID: u8 = 2;
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        rmp_serde::to_vec_named(value).map_err(|e| CodecError(e.to_string()))
+    }
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        rmp_serde::from_slice(bytes).map_err(|e| CodecError(e.to_string()))
+    }
+}
+
+/* The codecs that can be selected at runtime */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecKind {
+    Json,
+    Bincode,
+    MessagePack
+}
+
+impl CodecKind {
+    pub fn id(self) -> u8 {
+        match self {
+            CodecKind::Json => Json::ID,
+            CodecKind::Bincode => Bincode::ID,
+            CodecKind::MessagePack => MessagePack::ID,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<CodecKind> {
+        match id {
+            Json::ID => Some(CodecKind::Json),
+            Bincode::ID => Some(CodecKind::Bincode),
+            MessagePack::ID => Some(CodecKind::MessagePack),
+            _ => None
+        }
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            CodecKind::Json => Json::encode(value),
+            CodecKind::Bincode => Bincode::encode(value),
+            CodecKind::MessagePack => MessagePack::encode(value),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            CodecKind::Json => Json::decode(bytes),
+            CodecKind::Bincode => Bincode::decode(bytes),
+            CodecKind::MessagePack => MessagePack::decode(bytes),
+        }
+    }
+}
+
+static DEFAULT_CODEC: AtomicU8 = AtomicU8::new(Json::ID);
+
+thread_local! {
+    // set while an endpoint serves a request, so that results are encoded in the codec of the caller
+    static CURRENT_CODEC: Cell<Option<CodecKind>> = const { Cell::new(None) };
+}
+
+/* Selects the codec used by this process, call it at startup before any proxy is created */
+pub fn set_codec(kind: CodecKind) {
+    DEFAULT_CODEC.store(kind.id(), Ordering::Relaxed);
+}
+
+pub fn current_codec() -> CodecKind {
+    CURRENT_CODEC.with(|c| c.get())
+        .unwrap_or_else(|| CodecKind::from_id(DEFAULT_CODEC.load(Ordering::Relaxed)).unwrap())
+}
+
+/* Runs f with kind as the codec of the current thread */
+pub fn with_codec<R, F: FnOnce() -> R>(kind: CodecKind, f: F) -> R {
+    let previous = CURRENT_CODEC.with(|c| c.replace(Some(kind)));
+    // restores the previous codec even if f panics
+    struct Restore(Option<CodecKind>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT_CODEC.with(|c| c.set(self.0));
+        }
+    }
+    let _restore = Restore(previous);
+    f()
+}
+
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+    current_codec().encode(value)
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+    current_codec().decode(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::SystemTime;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn codecs_round_trip() {
+        let value = (SystemTime::now(), "127.0.0.1:3334".parse::<SocketAddr>().unwrap(), Some("text\n".to_string()), vec![1u32, 2, 3]);
+        for kind in [CodecKind::Json, CodecKind::Bincode, CodecKind::MessagePack] {
+            let bytes = kind.encode(&value).unwrap();
+            assert_eq!(kind.decode::<(SystemTime, SocketAddr, Option<String>, Vec<u32>)>(&bytes).unwrap(), value);
+            assert_eq!(CodecKind::from_id(kind.id()), Some(kind));
+        }
+    }
+
+    #[test]
+    fn thread_codec_overrides_default() {
+        with_codec(CodecKind::Bincode, || {
+            assert_eq!(current_codec(), CodecKind::Bincode);
+            assert_eq!(decode::<u32>(&encode(&7u32).unwrap()).unwrap(), 7);
+        });
+        assert_eq!(current_codec(), CodecKind::Json);
+    }
+}
@@ -1,36 +1,66 @@
 use std::thread;
 use std::any::Any;
-use std::net::{TcpListener, TcpStream, Shutdown, ToSocketAddrs};
 use std::collections::{HashMap};
-use std::time::SystemTime;
-use std::io::{Read, BufReader, BufRead};
+use std::time::{SystemTime, Duration, Instant};
+use std::io::{self, Read, BufReader, BufRead};
 use std::sync::{Arc, RwLock, Mutex, mpsc};
+use std::panic::{self, AssertUnwindSafe};
 use std::cell::{RefCell, Ref, RefMut};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use serde::{Serialize, Deserialize};
-use crate::message_serialisation::{Message, ReturnVar, send, response, InvokeOp};
+use crate::message_serialisation::{Message, ReturnVar, ErrorCode, CallError, send, response, InvokeOp};
 use crate::registry::{RegistryTable, Argument};
+use crate::resource_table::{Access};
+use crate::framing::{FrameDecoder, FrameError};
+use crate::codec::{CodecKind, encode, with_codec};
+use crate::transport::{Connection, Listener, ToAddress, listen};
+use crate::tls::{TlsServerConfig, listen_tls};
+use crate::proxy_lib::{SerializeTagL};
 
-use umi_macros::{Variable, ID};
+use umi_macros::{Variable, ID, Token, Address, Node, NodeId};
 
 //pub type ID = (SystemTime, usize);
 
-pub type ResourceTable = HashMap<ID, RefCell<(Box<dyn Any + Send + Sync>, bool)>>;
+pub use crate::resource_table::ResourceTable;
 
 pub struct IDGen {
-    id: usize
+    id: AtomicUsize
 }
 
 impl IDGen {
     pub fn new() -> IDGen {
-        IDGen { id: 0 }
+        IDGen { id: AtomicUsize::new(0) }
     }
 
-    pub fn next(&mut self) -> usize {
-        let curr = self.id;
-        self.id += 1;
-        curr
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&self) -> usize {
+        self.id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /* A fresh resource ID, its token is what a client has to present to use the resource */
+    pub fn next_id(&self) -> ID {
+        (SystemTime::now(), self.next(), random_token())
+    }
+}
+
+pub fn random_token() -> Token {
+    Token(random_bytes())
+}
+
+pub fn random_node_id() -> NodeId {
+    random_bytes()
+}
+
+fn random_bytes() -> [u8; 16] {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("the system random number generator is unavailable");
+    bytes
+}
+
+impl Default for IDGen {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -111,151 +141,191 @@ impl Worker {
     }
 }
 
+/* What happens to the resources a method was mutating when it panics */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    #[default]
+    Poison, // the resources may be half-updated, later requests using them fail with ErrorCode::ResourcePoisoned
+    Keep, // the resources stay usable as they are
+}
+
 pub struct UMIEndpoint {
-    listener: TcpListener,
+    listener: Box<dyn Listener>,
+    panic_policy: PanicPolicy,
+    lease: Option<Duration>,
+    node_id: NodeId,
+    advertised: Vec<Address>, // the addresses proxies dial, the bound address when empty
+    workers: usize,
+    idle_timeout: Option<Duration>,
+    read_timeout: Duration,
+}
+
+/* How long a resource created by a remote initialisation outlives the last renewal of its proxies */
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(30);
+
+/* How many requests an endpoint executes at once, each on a thread of its pool */
+pub const DEFAULT_WORKERS: usize = 64;
+
+/* How long a connection may wait for its next request before the endpoint closes it */
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/* How long a new connection may take to complete its TLS handshake and send its first request,
+ * and how long a request may stall before it arrives in full
+ */
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// how long a worker waits for the next request on a connection before parking it
+const LINGER: Duration = Duration::from_millis(5);
+
+// bounds on the pause between two polls of the parked connections, longer while they are all idle
+const MIN_POLL: Duration = Duration::from_millis(1);
+const MAX_POLL: Duration = Duration::from_millis(8);
+
+// Everything a worker needs to serve a request, shared between connections
+#[derive(Clone)]
+struct Dispatcher {
+    rtable: Arc<RegistryTable>, // read only once the endpoint is started
+    vtable: Arc<ResourceTable>,
+    node: Node, // carried by the proxies of the resources of this endpoint
+    local_address: Address,
+    panic_policy: PanicPolicy,
+    lease: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    read_timeout: Duration,
+}
+
+// a connection waiting for its next request, without a worker
+struct Parked {
+    reader: BufReader<Box<dyn Connection>>,
+    deadline: Option<Instant>, // when it is closed if no request arrived
 }
 
 impl UMIEndpoint {
-    pub fn new<A: ToSocketAddrs>(addr: A) -> UMIEndpoint {
+    /* Listens on a TCP address such as "127.0.0.1:3334" or a Unix domain socket such as "unix:/tmp/umi.sock" */
+    pub fn new<A: ToAddress>(addr: A) -> UMIEndpoint {
+        let addrs = addr.to_addresses().unwrap();
+        let mut last_error = None;
+        for addr in addrs {
+            match listen(&addr) {
+                Ok(listener) => return UMIEndpoint::with_listener(listener),
+                Err(e) => last_error = Some(e)
+            }
+        }
+        panic!("Unable to listen: {:?}", last_error)
+    }
+
+    /* Listens on a TLS address such as "tls:localhost:3334", presenting the certificate of config */
+    pub fn with_tls<A: ToAddress>(addr: A, config: TlsServerConfig) -> UMIEndpoint {
+        let addrs = addr.to_addresses().unwrap();
+        let mut last_error = None;
+        for addr in addrs {
+            match listen_tls(&addr, &config) {
+                Ok(listener) => return UMIEndpoint::with_listener(listener),
+                Err(e) => last_error = Some(e)
+            }
+        }
+        panic!("Unable to listen: {:?}", last_error)
+    }
+
+    fn with_listener(listener: Box<dyn Listener>) -> UMIEndpoint {
         UMIEndpoint {
-            listener: TcpListener::bind(addr).unwrap(),
-        }
-    }
-
-    pub fn start(&mut self, registry_table: RegistryTable, vtable: Arc<Mutex<ResourceTable>>) {
-        let id_gen = Arc::new(Mutex::new(IDGen::new()));
-        let rtable = Arc::new(Mutex::new(registry_table.clone()));
-        // bool - is an entry for resouce or reference
-        //let vtable: Arc<Mutex<ResourceTable>> = Arc::new(Mutex::new(resource_table));
-        let local_address = self.listener.local_addr().unwrap();
-
-        let pool = ThreadPool::new(5);
-        for stream in self.listener.incoming() {
-            let id_gen = Arc::clone(&id_gen);
-            let rtable = Arc::clone(&rtable);
-            let vtable = Arc::clone(&vtable);
-            match stream {
-                Ok(stream) => {
-                    println!("New connection: {}", stream.peer_addr().unwrap());
-                    pool.execute(move|| {
-                        // connection succeeded
-                        // deserialisation, invocation, serialise result, send back
-                        let mut reader = BufReader::new(&stream);
-                        let mut data = String::new();
-                        let mut m_id_gen = id_gen.lock().expect("Something went wrong with the id generator");
-                        let mut mvtable = vtable.lock().unwrap();
-                        let lrtable = rtable.lock().unwrap();
-                        match reader.read_line(&mut data) {
-                            Ok(size) => {
-                                let deserialised: Message = serde_json::from_str(&data).unwrap();
-                                //println!("{:?}", deserialised);
-                                match deserialised {
-                                    Message::Return(_) => {
-                                        println!("Not a valid invocation request");
-                                    },
-                                    Message::Drop(id) => {
-                                        println!("Dropping resource with id: {:?}", &id);
-                                        mvtable.remove(&id);
-                                        println!("The size of the vtable is: {}", mvtable.len());
-                                    },
-                                    Message::Invoke(fname, variables, invoke_op) => {
-                                        let mut arguments: Vec<Argument> = Vec::new();
-                                        for v in &variables {
-                                            match v {
-                                                Variable::OwnedLocal(s) => {
-                                                    arguments.push(Argument::Serialised(s.clone()));
-                                                },
-                                                Variable::OwnedRemote(serialise_remote, addr, id) => {
-                                                    if addr == &local_address { // the resource of a proxy indeed lives on this machine
-                                                        let (owned, is_ref) = mvtable.remove(id).unwrap().into_inner(); // is_ref here should never br true
-                                                        let arg_ref = Argument::Owned(owned);
-                                                        arguments.push(arg_ref);
-                                                    } else { // the resource of a remote proxy does no live on this machine -- just push the remote reference in, for later invocation
-                                                        arguments.push(Argument::Serialised(serialise_remote.to_string()));
-                                                    }
-                                                },
-                                                Variable::RefRemote(serialise_remote, addr, id) => {
-                                                    if addr == &local_address { // the resource of a remote reference indeed lives on this machine
-                                                        let borrow = mvtable.get(id).unwrap().borrow();
-                                                        let ptr: *const (Box<dyn Any + Send + Sync>, bool) = &*borrow;
-                                                        unsafe {
-                                                            let back: &(Box<dyn Any + Send + Sync>, bool) = ptr.as_ref().unwrap();
-                                                            let arg_ref = Argument::Ref(&back.0, back.1);
-                                                            arguments.push(arg_ref);
-                                                        }
-                                                    } else { // the resource of a remote reference does not live on this machine -- just push the remote reference in, for later invocation
-                                                        arguments.push(Argument::RemoteRef(serialise_remote.to_string()));
-                                                    }
-                                                },
-                                                Variable::MutRefRemote(serialise_remote, addr, id) => {
-                                                    if addr == &local_address { // the resource of a remote reference indeed lives on this machine
-                                                        let mut borrow_mut = mvtable.get(id).unwrap().borrow_mut();
-                                                        let ptr: *mut (Box<dyn Any + Send + Sync>, bool) = &mut *borrow_mut;
-                                                        unsafe {
-                                                            let back: &mut (Box<dyn Any + Send + Sync>, bool) = ptr.as_mut().unwrap();
-                                                            let arg_ref = Argument::MutRef(&mut back.0, back.1);
-                                                            arguments.push(arg_ref);
-                                                        }
-                                                    } else { // the resource of a remote reference does not live on this machine -- just push the remote reference in, for later invocation
-                                                        arguments.push(Argument::RemoteMutRef(serialise_remote.clone()));
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        let f: &str = &*fname; 
-                                        match lrtable.get(f) {
-                                            Some(f) => {
-                                                let ((res, is_local), b) = f.call(arguments);
-                                                let res_message: Message;
-                                                match invoke_op {
-                                                    InvokeOp::Owned => {
-                                                        // The result is pass back with the result or thr proxy:
-                                                        // - A::Local
-                                                        // - A::Remote
-                                                        res_message = Message::Return(ReturnVar::Owned(res));
-                                                    },
-                                                    InvokeOp::Init => {
-                                                        // This is the initalisation call, requiring a proxy to be sent back to the caller
-                                                        // while the resouce owned by the proxy is stored in the reserver:
-                                                        // - A::Remote
-                                                        let id = (SystemTime::now(), m_id_gen.next());
-                                                        mvtable.insert(id.clone(), RefCell::new((b, false))); // b is the resource
-                                                        res_message = Message::Return(ReturnVar::OwnedInit(local_address, id, true));
-                                                    },
-                                                    InvokeOp::Ref => { // borrow
-                                                        if is_local { // the local reference is boxed and inserted to the table, the proxy points to the local reference
-                                                            let id = (SystemTime::now(), m_id_gen.next());
-                                                            mvtable.insert(id.clone(), RefCell::new((b, true))); // b is a reference
-                                                            res_message = Message::Return(ReturnVar::RefOwned(local_address, id));
-                                                        } else {
-                                                            res_message = Message::Return(ReturnVar::RefBorrow(res));
-                                                        }
-                                                    },
-                                                    InvokeOp::MutRef => { // mutable borrow
-                                                        if is_local { // the local reference is boxed and inserted to the table, the proxy points to the local reference
-                                                            let id = (SystemTime::now(), m_id_gen.next());
-                                                            mvtable.insert(id.clone(), RefCell::new((b, true))); // b is a reference
-                                                            res_message = Message::Return(ReturnVar::MutRefOwned(local_address, id));
-                                                        } else {
-                                                            res_message = Message::Return(ReturnVar::MutRefBorrow(res));
-                                                        }
-                                                    }
-                                                }
-                                                response(stream, res_message);
-                                            },
-                                            None => {
-                                                println!("{}", "no such function found");
-                                            }
-                                        }
-                                    }
-                                }
-                            },
-                            Err(e) => {
-                                println!("An error: {} occurred, terminating connection", e);
-                                stream.shutdown(Shutdown::Both).unwrap();
-                            }
-                        }
-                    });
+            listener,
+            panic_policy: PanicPolicy::default(),
+            lease: Some(DEFAULT_LEASE),
+            node_id: random_node_id(),
+            advertised: Vec::new(),
+            workers: DEFAULT_WORKERS,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            read_timeout: DEFAULT_READ_TIMEOUT,
+        }
+    }
+
+    pub fn local_address(&self) -> Address {
+        self.listener.local_address().unwrap()
+    }
+
+    /* The identity of the endpoint, random unless set, it decides which resources are local to the endpoint */
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    // an endpoint restarted with the id it had keeps the proxies other endpoints stored local to it
+    pub fn set_node_id(&mut self, id: NodeId) {
+        self.node_id = id;
+    }
+
+    /* Adds an address that clients can dial, e.g. a public address when the endpoint is bound to 0.0.0.0
+     * or a forwarded port. Proxies carry the advertised addresses instead of the bound one
+     */
+    pub fn advertise<A: ToAddress>(&mut self, addr: A) {
+        self.advertised.extend(addr.to_addresses().unwrap());
+    }
+
+    pub fn node(&self) -> Node {
+        match self.advertised.is_empty() {
+            true => Node::new(self.node_id, vec![self.local_address()]),
+            false => Node::new(self.node_id, self.advertised.clone())
+        }
+    }
+
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = policy;
+    }
+
+    // None keeps the resources until their proxies drop them
+    pub fn set_lease(&mut self, lease: Option<Duration>) {
+        self.lease = lease;
+    }
+
+    // the requests past the limit wait until a worker is done with its request, idle connections hold no worker
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers;
+    }
+
+    // None keeps idle connections open until their clients close them
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn start(&mut self, registry_table: RegistryTable, vtable: Arc<ResourceTable>) {
+        let dispatcher = Dispatcher {
+            rtable: Arc::new(registry_table),
+            vtable,
+            node: self.node(),
+            local_address: self.local_address(),
+            panic_policy: self.panic_policy,
+            lease: self.lease,
+            idle_timeout: self.idle_timeout,
+            read_timeout: self.read_timeout,
+        };
+
+        if let Some(lease) = self.lease {
+            // the resources of clients that stopped renewing, e.g. because they crashed, are reclaimed
+            let vtable = Arc::clone(&dispatcher.vtable);
+            thread::spawn(move || loop {
+                thread::sleep((lease / 4).max(Duration::from_millis(10)));
+                for id in vtable.reap_leases(Instant::now()) {
+                    println!("The lease of resource with id: {:?} expired", id);
+                }
+            });
+        }
+
+        // a new connection is parked until its first request arrives, so that a client that never sends one holds no worker
+        let (parker, parked) = mpsc::channel();
+        let pool = ThreadPool::new(self.workers);
+        let parking = dispatcher.clone();
+        let returned = parker.clone();
+        thread::spawn(move || parking.park_connections(parked, returned, pool));
+        loop {
+            match self.listener.accept() {
+                Ok((conn, peer)) => {
+                    println!("New connection: {}", peer);
+                    let deadline = Some(Instant::now() + self.read_timeout);
+                    let _ = parker.send(Parked { reader: BufReader::new(conn), deadline });
                 }
                 Err(e) => {
                     println!("Error: {}", e);
@@ -265,38 +335,439 @@ impl UMIEndpoint {
         }
     }
 
-    pub fn close(&self) {
-        drop(&self.listener);
+    pub fn close(self) {
+        drop(self.listener);
+    }
+}
+
+impl Dispatcher {
+    /* Polls the parked connections and hands those with a request to a worker, which parks them again once idle.
+     * Connections past their deadline or closed by their clients are dropped
+     */
+    fn park_connections(&self, parked: mpsc::Receiver<Parked>, returned: mpsc::Sender<Parked>, pool: ThreadPool) {
+        let mut waiting: Vec<Parked> = Vec::new();
+        let mut pause = MIN_POLL;
+        loop {
+            let received = match waiting.is_empty() {
+                true => parked.recv().ok(),
+                false => parked.recv_timeout(pause).ok()
+            };
+            let mut active = false;
+            for conn in received.into_iter().chain(parked.try_iter()) {
+                match conn.reader.get_ref().set_nonblocking(true) {
+                    Ok(()) => waiting.push(conn),
+                    Err(e) => println!("An error: {} occurred, terminating connection", e)
+                }
+            }
+            let now = Instant::now();
+            for mut conn in std::mem::take(&mut waiting) {
+                match has_request(&mut conn.reader) {
+                    Ok(true) => {
+                        active = true;
+                        if let Err(e) = conn.reader.get_ref().set_nonblocking(false) {
+                            println!("An error: {} occurred, terminating connection", e);
+                            continue;
+                        }
+                        let dispatcher = self.clone();
+                        let returned = returned.clone();
+                        pool.execute(move || {
+                            if let Some(reader) = dispatcher.serve_connection(conn.reader) {
+                                let deadline = dispatcher.idle_timeout.map(|timeout| Instant::now() + timeout);
+                                let _ = returned.send(Parked { reader, deadline });
+                            }
+                        });
+                    },
+                    Ok(false) if conn.deadline.is_none_or(|deadline| now < deadline) => waiting.push(conn),
+                    Ok(false) => {
+                        println!("Closing an idle connection");
+                        let _ = conn.reader.get_ref().shutdown();
+                    },
+                    Err(e) => println!("Connection closed: {}", e)
+                }
+            }
+            pause = match active {
+                true => MIN_POLL,
+                false => (pause * 2).min(MAX_POLL)
+            };
+        }
+    }
+
+    /* Serves the requests of a connection in turn, returns it once no request follows shortly */
+    fn serve_connection(&self, mut reader: BufReader<Box<dyn Connection>>) -> Option<BufReader<Box<dyn Connection>>> {
+        let decoder = FrameDecoder::default();
+        loop {
+            // a request that started is read and answered within the read timeout
+            if let Err(e) = reader.get_ref().set_timeout(Some(self.read_timeout)) {
+                println!("An error: {} occurred, terminating connection", e);
+                break;
+            }
+            match decoder.read_frame(&mut reader) {
+                Ok(frame) => {
+                    // the request is answered with the codec it was encoded with
+                    let codec = match CodecKind::from_id(frame.flags) {
+                        Some(codec) => codec,
+                        None => {
+                            println!("Unknown codec: {}", frame.flags);
+                            // the client cannot be answered in its codec, the error is sent in the default one before closing
+                            let msg = Message::Error(ErrorCode::UnsupportedCodec, format!("codec id {}", frame.flags));
+                            let _ = response(reader.get_mut(), CodecKind::Json, msg);
+                            break;
+                        }
+                    };
+                    let res_message = with_codec(codec, || match codec.decode::<Message>(&frame.payload) {
+                        Ok(deserialised) => self.serve(deserialised),
+                        Err(e) => Message::Error(ErrorCode::MalformedMessage, e.0)
+                    });
+                    if let Err(e) = response(reader.get_mut(), codec, res_message) {
+                        println!("An error: {} occurred, terminating connection", e);
+                        break;
+                    }
+                    match wait_for_request(&mut reader, LINGER) {
+                        Ok(true) => {},
+                        Ok(false) => return Some(reader),
+                        Err(e) => {
+                            println!("Connection closed: {}", e);
+                            break;
+                        }
+                    }
+                },
+                Err(FrameError::Closed) => {
+                    println!("Connection closed by client");
+                    break;
+                },
+                Err(e) => {
+                    println!("An error: {} occurred, terminating connection", e);
+                    break;
+                }
+            }
+        }
+        let _ = reader.get_ref().shutdown();
+        None
+    }
+
+    /* Serves a request on the current thread. The requests it makes to this endpoint are executed in place, see dispatch_local */
+    fn serve(&self, msg: Message) -> Message {
+        let previous = SERVING.with(|s| s.replace(Some(self.clone())));
+        struct Restore(Option<Dispatcher>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                SERVING.with(|s| *s.borrow_mut() = self.0.take());
+            }
+        }
+        let _restore = Restore(previous);
+        // invoke() already catches panics of methods, this keeps the worker alive on any other
+        panic::catch_unwind(AssertUnwindSafe(|| self.handle(msg)))
+            .unwrap_or_else(|payload| Message::Error(ErrorCode::RemotePanic, panic_message(&*payload)))
+    }
+
+    // deserialisation, invocation, serialise result
+    fn handle(&self, deserialised: Message) -> Message {
+        //println!("{:?}", deserialised);
+        match deserialised {
+            Message::Invoke(fname, variables, invoke_op) => {
+                match self.invoke(fname, variables, invoke_op) {
+                    Ok(res_message) => res_message,
+                    Err((code, detail)) => {
+                        println!("Invocation failed: {:?} {}", code, detail);
+                        Message::Error(code, detail)
+                    }
+                }
+            },
+            Message::Drop(id) if self.vtable.is_bound(&id) => {
+                // the proxies of a bound resource, even its creator's, do not own it
+                Message::Return(ReturnVar::Owned(().tagged_bytes().0))
+            },
+            Message::Drop(id) => {
+                println!("Dropping resource with id: {:?}", &id);
+                match self.vtable.remove(&id) {
+                    true => {
+                        println!("The size of the vtable is: {}", self.vtable.len());
+                        Message::Return(ReturnVar::Owned(().tagged_bytes().0))
+                    },
+                    false => Message::Error(ErrorCode::UnknownResource, format!("{:?}", id))
+                }
+            },
+            Message::Release(id) => {
+                println!("Releasing borrow with id: {:?}", &id);
+                match self.vtable.release(&id) {
+                    true => Message::Return(ReturnVar::Owned(().tagged_bytes().0)),
+                    false => Message::Error(ErrorCode::UnknownResource, format!("{:?} is not a borrow", id))
+                }
+            },
+            Message::Renew(ids) => {
+                let unknown = match self.lease {
+                    Some(lease) => self.vtable.renew_leases(&ids, lease),
+                    None => ids.into_iter().filter(|id| !self.vtable.contains(id)).collect()
+                };
+                Message::Return(ReturnVar::Owned(encode(&unknown).unwrap()))
+            },
+            Message::Attach(name) => {
+                match self.vtable.lookup(&name) {
+                    Some(id) => Message::Return(ReturnVar::OwnedInit(self.node.clone(), id, false)),
+                    None => Message::Error(ErrorCode::UnknownName, name)
+                }
+            },
+            Message::Names => Message::Return(ReturnVar::Owned(encode(&self.vtable.names()).unwrap())),
+            other => {
+                println!("Not a valid invocation request");
+                Message::Error(ErrorCode::UnexpectedMessage, format!("{:?}", other))
+            }
+        }
+    }
+
+    fn invoke(&self, fname: String, variables: Vec<Variable>, invoke_op: InvokeOp) -> Result<Message, CallError> {
+        let local_node = &self.node;
+        let f = self.rtable.get(fname.as_str()).ok_or_else(|| {
+            println!("no such function found");
+            (ErrorCode::UnknownFunction, fname.clone())
+        })?;
+        // the local resources are locked for the whole call, following how each variable borrows them
+        let mut requests = Vec::new();
+        for v in &variables {
+            match v {
+                Variable::OwnedRemote(_, node, id) if node == local_node => requests.push((*id, Access::Take)),
+                Variable::RefRemote(_, node, id) if node == local_node => requests.push((*id, Access::Shared)),
+                Variable::MutRefRemote(_, node, id) if node == local_node => requests.push((*id, Access::Exclusive)),
+                _ => {}
+            }
+        }
+        let borrows = self.vtable.borrow_all(&requests)?;
+        // a returned reference can only point into the resources the method borrowed
+        let sources: Vec<ID> = requests.iter().filter(|(_, access)| *access != Access::Take).map(|(id, _)| *id).collect();
+        let mut arguments: Vec<Argument> = Vec::new();
+        for v in &variables {
+            match v {
+                Variable::OwnedLocal(s) => {
+                    arguments.push(Argument::Serialised(s.clone()));
+                },
+                Variable::OwnedRemote(serialise_remote, node, id) => {
+                    if node == local_node { // the resource of a proxy indeed lives on this machine
+                        let back = borrows.get_taken(id).unwrap(); // back.1 here should never be true
+                        arguments.push(Argument::Owned(&back.0));
+                    } else { // the resource of a remote proxy does no live on this machine -- just push the remote reference in, for later invocation
+                        // the proxy was moved here, its previous owner stops renewing and dropping the resource
+                        arguments.push(Argument::Moved(serialise_remote.clone()));
+                    }
+                },
+                Variable::RefRemote(serialise_remote, node, id) => {
+                    if node == local_node { // the resource of a remote reference indeed lives on this machine
+                        let back = borrows.get(id).unwrap();
+                        arguments.push(Argument::Ref(&back.0, back.1));
+                    } else { // the resource of a remote reference does not live on this machine -- just push the remote reference in, for later invocation
+                        arguments.push(Argument::RemoteRef(serialise_remote.clone()));
+                    }
+                },
+                Variable::MutRefRemote(serialise_remote, node, id) => {
+                    if node == local_node { // the resource of a remote reference indeed lives on this machine
+                        let back = borrows.get_mut(id).unwrap();
+                        arguments.push(Argument::MutRef(&mut back.0, back.1));
+                    } else { // the resource of a remote reference does not live on this machine -- just push the remote reference in, for later invocation
+                        arguments.push(Argument::RemoteMutRef(serialise_remote.clone()));
+                    }
+                }
+            }
+        }
+        // a panicking method is unwound here, before the table guards, so that the tables are not poisoned
+        let ((res, is_local), b) = match panic::catch_unwind(AssertUnwindSafe(|| f.call(arguments))) {
+            Ok(result) => result?,
+            Err(payload) => {
+                if self.panic_policy == PanicPolicy::Poison {
+                    borrows.poison_exclusive();
+                }
+                return Err((ErrorCode::RemotePanic, panic_message(&*payload)));
+            }
+        };
+        let res_message: Message;
+        match invoke_op {
+            InvokeOp::Owned => {
+                // The result is pass back with the result or thr proxy:
+                // - A::Local
+                // - A::Remote
+                res_message = Message::Return(ReturnVar::Owned(res));
+            },
+            InvokeOp::Init => {
+                // This is the initalisation call, requiring a proxy to be sent back to the caller
+                // while the resouce owned by the proxy is stored in the reserver:
+                // - A::Remote
+                let id = self.vtable.next_id();
+                self.vtable.insert(id, b, false); // b is the resource
+                if let Some(lease) = self.lease {
+                    self.vtable.lease(id, lease);
+                }
+                res_message = Message::Return(ReturnVar::OwnedInit(local_node.clone(), id, true));
+            },
+            InvokeOp::InitNamed(name) => {
+                // the resource belongs to its name, the caller gets a proxy that does not own it
+                let id = self.vtable.next_id();
+                self.vtable.insert(id, b, false);
+                if !self.vtable.bind(&name, id) {
+                    self.vtable.remove(&id);
+                    return Err((ErrorCode::NameTaken, name));
+                }
+                res_message = Message::Return(ReturnVar::OwnedInit(local_node.clone(), id, false));
+            },
+            InvokeOp::Ref => { // borrow
+                if is_local { // the local reference is boxed and inserted to the table, the proxy points to the local reference
+                    let id = self.vtable.next_id();
+                    self.vtable.insert_borrow(id, b, &sources); // b is a reference into the borrowed resources
+                    res_message = Message::Return(ReturnVar::RefOwned(local_node.clone(), id));
+                } else {
+                    res_message = Message::Return(ReturnVar::RefBorrow(res));
+                }
+            },
+            InvokeOp::MutRef => { // mutable borrow
+                if is_local { // the local reference is boxed and inserted to the table, the proxy points to the local reference
+                    let id = self.vtable.next_id();
+                    self.vtable.insert_borrow(id, b, &sources); // b is a reference into the borrowed resources
+                    res_message = Message::Return(ReturnVar::MutRefOwned(local_node.clone(), id));
+                } else {
+                    res_message = Message::Return(ReturnVar::MutRefBorrow(res));
+                }
+            }
+        }
+        // the resources moved into the call are only gone when the request succeeded
+        self.vtable.remove_taken(&borrows);
+        Ok(res_message)
+    }
+}
+
+thread_local! {
+    // the endpoint whose request the current thread is serving
+    static SERVING: RefCell<Option<Dispatcher>> = const { RefCell::new(None) };
+}
+
+/* Executes a request in place when it is addressed to the endpoint the current thread is serving.
+ * Sent through the network, a method calling back into its own endpoint would wait for a free worker
+ * and for the resources it holds itself. Returns the request back when it is for another endpoint.
+ * The endpoint is recognised by its node id when the request names one, by its addresses otherwise
+ */
+pub(crate) fn dispatch_local(node_id: Option<NodeId>, addrs: &[Address], msg: Message) -> Result<Message, Message> {
+    let dispatcher = SERVING.with(|s| s.borrow().clone());
+    match dispatcher {
+        Some(dispatcher) if is_served_by(&dispatcher, node_id, addrs) => Ok(dispatcher.serve(msg)),
+        _ => Err(msg)
+    }
+}
+
+fn is_served_by(dispatcher: &Dispatcher, node_id: Option<NodeId>, addrs: &[Address]) -> bool {
+    match node_id {
+        Some(id) => id == dispatcher.node.id,
+        None => addrs.iter().any(|addr| addr == &dispatcher.local_address || dispatcher.node.addrs.contains(addr))
     }
 }
 
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(s) => s.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(s) => s.clone(),
+            None => "unknown panic".to_string()
+        }
+    }
+}
+
+// whether a request waits to be read, false when nothing arrived within the timeout or on a nonblocking connection
+fn has_request(reader: &mut BufReader<Box<dyn Connection>>) -> io::Result<bool> {
+    match reader.fill_buf() {
+        Ok([]) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "closed by client")),
+        Ok(_) => Ok(true),
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => Ok(false),
+        Err(e) => Err(e)
+    }
+}
+
+fn wait_for_request(reader: &mut BufReader<Box<dyn Connection>>, timeout: Duration) -> io::Result<bool> {
+    reader.get_ref().set_timeout(Some(timeout))?;
+    has_request(reader)
+}
+
+/* Initialises a resource on the endpoint at addr with fn_name, and returns the proxy of the return type,
+ * e.g. remote!(addr, StudentRecord::new, StudentRecord) is a StudentRecordProxy
+ */
 #[macro_export]
 macro_rules! remote {
     ($addr:expr, $fn_name:path, $return_ty:ty $(, $x:expr )*) => { 
-        {
+        $crate::try_remote!($addr, $fn_name, $return_ty $(, $x)*).unwrap()
+    };
+}
+
+/* Like remote!, but a failed initialisation is returned as a UmiError instead of panicking */
+#[macro_export]
+macro_rules! try_remote {
+    ($addr:expr, $fn_name:path, $return_ty:ty $(, $x:expr )*) => { 
+        $crate::try_remote_op!($addr, InvokeOp::Init, $fn_name, $return_ty $(, $x)*)
+    };
+}
+
+/* Initialises a resource bound to a name on the endpoint, so that other clients can attach! to it.
+ * The resource belongs to the name, the returned proxy does not drop it
+ */
+#[macro_export]
+macro_rules! remote_named {
+    ($addr:expr, $name:expr, $fn_name:path, $return_ty:ty $(, $x:expr )*) => { 
+        $crate::try_remote_named!($addr, $name, $fn_name, $return_ty $(, $x)*).unwrap()
+    };
+}
+
+#[macro_export]
+macro_rules! try_remote_named {
+    ($addr:expr, $name:expr, $fn_name:path, $return_ty:ty $(, $x:expr )*) => { 
+        $crate::try_remote_op!($addr, InvokeOp::InitNamed(String::from($name)), $fn_name, $return_ty $(, $x)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! try_remote_op {
+    ($addr:expr, $op:expr, $fn_name:path, $return_ty:ty $(, $x:expr )*) => { 
+        (|| -> Result<<$return_ty as $crate::proxy_lib::Proxied>::Proxy, UmiError> {
             let mut vec = Vec::new();
             $(
                 vec.push($x.to_variable());
             )*
-            let msg = Message::Invoke(fn_type_name(&$fn_name).to_string(), vec, InvokeOp::Init);
-            let res = send($addr, msg).unwrap();
-            let res_msg : Message = serde_json::from_str(&res).unwrap();
-            let result: $return_ty;
-            match res_msg {
-                Message::Return(var) => {
-                    match var {
-                        ReturnVar::Owned(s) => {
-                            result = serde_json::from_str(&s).unwrap();
-                        },
-                        ReturnVar::OwnedInit(addr, id, is_owner) => {
-                            result = <$return_ty>::Remote(addr, id, Arc::new(AtomicBool::new(is_owner)));
-                        },
-                        _ => {panic!("Invalid return value")}     
-                    }
-                },
-                _ => {panic!("I am expecting a return message.")}
-            }
-            result
+            let addr = $addr;
+            let msg = Message::Invoke(fn_type_name(&$fn_name).to_string(), vec, $op);
+            let returned = invoke(&addr, msg)?;
+            $crate::proxy_from_return!(addr, returned, $return_ty)
+        })()
+    };
+}
+
+/* A proxy of the resource bound to name on the endpoint at addr, which does not own the resource */
+#[macro_export]
+macro_rules! attach {
+    ($addr:expr, $name:expr, $return_ty:ty) => { 
+        $crate::try_attach!($addr, $name, $return_ty).unwrap()
+    };
+}
+
+#[macro_export]
+macro_rules! try_attach {
+    ($addr:expr, $name:expr, $return_ty:ty) => { 
+        (|| -> Result<<$return_ty as $crate::proxy_lib::Proxied>::Proxy, UmiError> {
+            let addr = $addr;
+            let returned = invoke(&addr, Message::Attach(String::from($name)))?;
+            $crate::proxy_from_return!(addr, returned, $return_ty)
+        })()
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! proxy_from_return {
+    ($addr:ident, $returned:ident, $return_ty:ty) => { 
+        match $returned {
+            ReturnVar::Owned(s) => Ok(decode(&s)?),
+            ReturnVar::OwnedInit(node, id, is_owner) => {
+                // the address the endpoint was reached at is dialed first, it may not know how clients see it
+                let node = node.reached_at(&$crate::transport::ToAddress::to_addresses(&$addr)?);
+                let owner = Arc::new(AtomicBool::new(is_owner));
+                if is_owner {
+                    $crate::lease::hold_lease(&node, id, &owner);
+                }
+                Ok(<<$return_ty as $crate::proxy_lib::Proxied>::Proxy as $crate::proxy_lib::Proxy>::construct_remote(node, id, owner))
+            },
+            var => Err(UmiError::InvalidResponse(format!("{:?}", var)))
         }
     };
 }
@@ -304,7 +775,413 @@ macro_rules! remote {
 
 #[cfg(test)]
 mod test {
-    use crate::endpoint::{UMIEndpoint};
+    use crate::endpoint::{UMIEndpoint, ResourceTable, PanicPolicy, random_node_id};
+    use serde::{Serialize, Deserialize};
+    use crate::message_serialisation::{Message, ReturnVar, InvokeOp, ErrorCode, send, invoke, with_request_timeout};
+    use crate::framing::{write_frame, FrameDecoder};
+    use crate::codec::encode;
+    use umi_macros::{Variable, ID, Token};
+    use std::time::SystemTime;
+    use std::net::TcpStream;
+    use std::io::{self, Read, Write};
+    use crate::error::UmiError;
+    use crate::registry::*;
+    use crate::proxy_lib::*;
+    use crate::utils::{fn_type_name};
+    use crate::codec::{CodecKind, decode, with_codec};
+    use crate::transport::{ToAddress};
+    use umi_macros::{Address, Node};
+    use crate::register;
     use std::any::Any;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
 
-}
\ No newline at end of file
+    fn ping() -> bool {
+        true
+    }
+
+    fn is_even(x: u32) -> bool {
+        x.is_multiple_of(2)
+    }
+
+    // a resource whose method panics on its second call
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Counter(u32);
+
+    impl BorrowRemote for Counter {
+        fn borrow_remote(&self) -> Self {
+            panic!("This should never be called");
+        }
+    }
+
+    impl SerializeTagL for Counter {
+        fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+            (encode(self).unwrap(), true)
+        }
+    }
+
+    fn counter_new() -> Counter {
+        Counter(0)
+    }
+
+    // refuses to start a counter past its limit
+    fn counter_open(start: u32) -> Result<Counter, String> {
+        if start > 9 {
+            return Err(format!("{} is past the limit", start));
+        }
+        Ok(Counter(start))
+    }
+
+    fn counter_bump(counter: &mut Counter) -> bool {
+        counter.0 += 1;
+        if counter.0 > 1 {
+            panic!("counter overflow");
+        }
+        true
+    }
+
+    // consumes the counter, panics when asked to
+    fn counter_consume(counter: Counter, fail: bool) -> u32 {
+        if fail {
+            panic!("consume failed");
+        }
+        counter.0
+    }
+
+    impl BorrowRemote for Node {
+        fn borrow_remote(&self) -> Self {
+            panic!("This should never be called");
+        }
+    }
+
+    impl BorrowRemote for ID {
+        fn borrow_remote(&self) -> Self {
+            panic!("This should never be called");
+        }
+    }
+
+    // bumps the counter target of the endpoint at addr while counter is borrowed mutably
+    fn counter_relay(_counter: &mut Counter, addr: Node, target: ID) -> Result<(), UmiError> {
+        bump(&addr, target).map(|_| ())
+    }
+
+    // calls itself through the endpoint at addr until depth reaches zero
+    fn chain(addr: Node, depth: u32) -> bool {
+        if depth == 0 {
+            return true;
+        }
+        let args = vec![Variable::OwnedLocal(encode(&addr).unwrap()), Variable::OwnedLocal(encode(&(depth - 1)).unwrap())];
+        let msg = Message::Invoke(fn_type_name(&chain).to_string(), args, InvokeOp::Owned);
+        match invoke(&addr, msg).unwrap() {
+            ReturnVar::Owned(s) => decode::<bool>(&s).unwrap(),
+            var => panic!("Unexpected return {:?}", var)
+        }
+    }
+
+    // takes each kind of argument, more of them than register! used to support
+    fn counter_mix(target: &mut Counter, source: &Counter, a: u32, b: u32, c: u32, label: String) -> Option<String> {
+        target.0 = source.0 + a + b + c;
+        Some(format!("{} {}", label, target.0))
+    }
+
+    fn start_counter_server(policy: PanicPolicy) -> Node {
+        let mut server = UMIEndpoint::new("127.0.0.1:0");
+        server.set_panic_policy(policy);
+        serve(counter_table(), server)
+    }
+
+    fn counter_table() -> RegistryTable {
+        let mut table = RegistryTable::new();
+        register!(table, Ping, ping, fn() -> bool, (bool, ResultOp::Owned));
+        register!(table, CounterNew, counter_new, fn() -> Counter, (Counter, ResultOp::Owned));
+        register!(table, CounterOpen, counter_open, fn(u32) -> Result<Counter, String>, (Counter, Result<Counter, String>, ResultOp::TryInit), u32, u32);
+        register!(table, CounterBump, counter_bump, fn(&mut Counter) -> bool, (bool, ResultOp::Owned), Counter, &mut Counter);
+        register!(table, CounterConsume, counter_consume, fn(Counter, bool) -> u32, (u32, ResultOp::Owned), Counter, bool, Counter, bool);
+        register!(table, CounterRelay, counter_relay, fn(&mut Counter, Node, ID) -> Result<(), UmiError>, (Result<(), UmiError>, ResultOp::Owned), Counter, Node, ID, &mut Counter, Node, ID);
+        register!(table, Chain, chain, fn(Node, u32) -> bool, (bool, ResultOp::Owned), Node, u32, Node, u32);
+        register!(table, CounterMix, counter_mix, fn(&mut Counter, &Counter, u32, u32, u32, String) -> Option<String>, (Option<String>, ResultOp::Owned),
+            Counter, Counter, u32, u32, u32, String, &mut Counter, &Counter, u32, u32, u32, String);
+        table
+    }
+
+    fn bump(addr: &Node, id: ID) -> Result<ReturnVar, UmiError> {
+        let msg = Message::Invoke(fn_type_name(&counter_bump).to_string(), vec![Variable::MutRefRemote(vec![], addr.clone(), id)], InvokeOp::Owned);
+        invoke(addr, msg)
+    }
+
+    fn relay(addr: &Node, from: ID, to: ID) -> Result<(), UmiError> {
+        let args = vec![Variable::MutRefRemote(vec![], addr.clone(), from), Variable::OwnedLocal(encode(addr).unwrap()), Variable::OwnedLocal(encode(&to).unwrap())];
+        let msg = Message::Invoke(fn_type_name(&counter_relay).to_string(), args, InvokeOp::Owned);
+        match invoke(addr, msg).unwrap() {
+            ReturnVar::Owned(s) => decode::<Result<(), UmiError>>(&s).unwrap(),
+            var => panic!("Unexpected return {:?}", var)
+        }
+    }
+
+    fn new_counter(addr: &Node) -> ID {
+        let msg = Message::Invoke(fn_type_name(&counter_new).to_string(), vec![], InvokeOp::Init);
+        match invoke(addr, msg).unwrap() {
+            ReturnVar::OwnedInit(_, id, _) => id,
+            var => panic!("Unexpected return {:?}", var)
+        }
+    }
+
+    fn start_server<A: ToAddress>(addr: A) -> Node {
+        let mut table = RegistryTable::new();
+        register!(table, Ping, ping, fn() -> bool, (bool, ResultOp::Owned));
+        register!(table, IsEven, is_even, fn(u32) -> bool, (bool, ResultOp::Owned), u32, u32);
+        serve(table, UMIEndpoint::new(addr))
+    }
+
+    fn serve(table: RegistryTable, server: UMIEndpoint) -> Node {
+        serve_with(table, server, Arc::new(ResourceTable::new()))
+    }
+
+    fn serve_with(table: RegistryTable, mut server: UMIEndpoint, vtable: Arc<ResourceTable>) -> Node {
+        let node = server.node();
+        thread::spawn(move || server.start(table, vtable));
+        node
+    }
+
+    fn call_ping<A: ToAddress>(addr: A) -> bool {
+        let msg = Message::Invoke(fn_type_name(&ping).to_string(), vec![], InvokeOp::Owned);
+        match decode(&send(addr, msg).unwrap()).unwrap() {
+            Message::Return(ReturnVar::Owned(s)) => decode::<bool>(&s).unwrap(),
+            _ => panic!("Expecting a return message")
+        }
+    }
+
+    #[test]
+    fn endpoint_answers_in_the_codec_of_the_request() {
+        let addr = start_server("127.0.0.1:0");
+        for kind in [CodecKind::Json, CodecKind::Bincode, CodecKind::MessagePack] {
+            assert!(with_codec(kind, || call_ping(&addr)));
+        }
+    }
+
+    fn error_code(reply: Vec<u8>) -> ErrorCode {
+        match decode(&reply).unwrap() {
+            Message::Error(code, _) => code,
+            m => panic!("Expecting an error message, got {:?}", m)
+        }
+    }
+
+    #[test]
+    fn endpoint_answers_failures_with_error_messages() {
+        let addr = start_server("127.0.0.1:0");
+        let msg = Message::Invoke("no::such::function".to_string(), vec![], InvokeOp::Owned);
+        assert!(matches!(invoke(&addr, msg), Err(UmiError::UnknownFunction(_))));
+
+        let unknown_id = (SystemTime::now(), 42, Token([0; 16]));
+        assert_eq!(error_code(send(&addr, Message::Drop(unknown_id)).unwrap()), ErrorCode::UnknownResource);
+        let msg = Message::Return(ReturnVar::Owned(vec![]));
+        assert_eq!(error_code(send(&addr, msg).unwrap()), ErrorCode::UnexpectedMessage);
+
+        let is_even_name = fn_type_name(&is_even).to_string();
+        let msg = Message::Invoke(is_even_name.clone(), vec![Variable::OwnedLocal(encode("four").unwrap())], InvokeOp::Owned);
+        assert_eq!(error_code(send(&addr, msg).unwrap()), ErrorCode::ArgumentMismatch);
+        let msg = Message::Invoke(is_even_name.clone(), vec![], InvokeOp::Owned);
+        assert_eq!(error_code(send(&addr, msg).unwrap()), ErrorCode::ArgumentMismatch);
+        // extra leading arguments are refused, not ignored
+        let four = || Variable::OwnedLocal(encode(&4u32).unwrap());
+        let msg = Message::Invoke(is_even_name.clone(), vec![four(), four()], InvokeOp::Owned);
+        assert_eq!(error_code(send(&addr, msg).unwrap()), ErrorCode::ArgumentMismatch);
+        let msg = Message::Invoke(is_even_name.clone(), vec![Variable::RefRemote(vec![], addr.clone(), unknown_id)], InvokeOp::Owned);
+        assert_eq!(error_code(send(&addr, msg).unwrap()), ErrorCode::UnknownResource);
+
+        // the endpoint keeps serving on the same connection
+        let msg = Message::Invoke(is_even_name, vec![Variable::OwnedLocal(encode(&4u32).unwrap())], InvokeOp::Owned);
+        match invoke(&addr, msg).unwrap() {
+            ReturnVar::Owned(s) => assert!(decode::<bool>(&s).unwrap()),
+            var => panic!("Unexpected return {:?}", var)
+        }
+    }
+
+    #[test]
+    fn endpoint_answers_malformed_messages() {
+        let addr = match start_server("127.0.0.1:0").addrs[0].clone() {
+            Address::Tcp(addr) => addr,
+            addr => panic!("Unexpected address {}", addr)
+        };
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write_frame(&mut stream, CodecKind::Json.id(), b"{not a message").unwrap();
+        let reply = FrameDecoder::default().read_frame(&mut stream).unwrap();
+        assert_eq!(error_code(reply.payload), ErrorCode::MalformedMessage);
+
+        write_frame(&mut stream, 200, b"").unwrap();
+        let reply = FrameDecoder::default().read_frame(&mut stream).unwrap();
+        assert_eq!(error_code(reply.payload), ErrorCode::UnsupportedCodec);
+    }
+
+    #[test]
+    fn idle_connections_do_not_hold_workers() {
+        let mut table = RegistryTable::new();
+        register!(table, Ping, ping, fn() -> bool, (bool, ResultOp::Owned));
+        let mut server = UMIEndpoint::new("127.0.0.1:0");
+        server.set_workers(2);
+        let addr = match server.local_address() {
+            Address::Tcp(addr) => addr,
+            addr => panic!("Unexpected address {}", addr)
+        };
+        serve(table, server);
+
+        // connections that were served once and connections that never send anything, all left open
+        let mut idle = Vec::new();
+        for _ in 0..4 {
+            let mut served = TcpStream::connect(addr).unwrap();
+            write_frame(&mut served, CodecKind::Json.id(), b"{not a message").unwrap();
+            assert!(FrameDecoder::default().read_frame(&mut served).is_ok());
+            idle.push(served);
+            idle.push(TcpStream::connect(addr).unwrap());
+        }
+        let result = with_request_timeout(Some(Duration::from_secs(5)), || {
+            let msg = Message::Invoke(fn_type_name(&ping).to_string(), vec![], InvokeOp::Owned);
+            send(addr, msg)
+        });
+        assert!(matches!(decode(&result.unwrap()).unwrap(), Message::Return(_)));
+    }
+
+    #[test]
+    fn silent_connections_are_closed() {
+        let mut server = UMIEndpoint::new("127.0.0.1:0");
+        server.set_idle_timeout(Some(Duration::from_millis(100)));
+        server.set_read_timeout(Duration::from_millis(100));
+        let addr = match server.local_address() {
+            Address::Tcp(addr) => addr,
+            addr => panic!("Unexpected address {}", addr)
+        };
+        serve(RegistryTable::new(), server);
+
+        let mut served = TcpStream::connect(addr).unwrap();
+        write_frame(&mut served, CodecKind::Json.id(), b"{not a message").unwrap();
+        assert!(FrameDecoder::default().read_frame(&mut served).is_ok());
+        // a client that never sends its first request, e.g. one stalling its TLS handshake
+        let mut silent = TcpStream::connect(addr).unwrap();
+        // a request that never arrives in full
+        let mut partial = TcpStream::connect(addr).unwrap();
+        partial.write_all(&[0; 3]).unwrap();
+
+        for conn in [&mut served, &mut silent, &mut partial] {
+            conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            match conn.read(&mut [0; 1]) {
+                Ok(n) => assert_eq!(n, 0),
+                Err(e) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset)
+            }
+        }
+    }
+
+    #[test]
+    fn panicking_method_poisons_its_resource() {
+        let addr = start_counter_server(PanicPolicy::Poison);
+        let id = new_counter(&addr);
+        let other = new_counter(&addr);
+        assert!(bump(&addr, id).is_ok());
+        match bump(&addr, id) {
+            Err(UmiError::RemotePanic(detail)) => assert_eq!(detail, "counter overflow"),
+            r => panic!("Unexpected result {:?}", r)
+        }
+        assert!(matches!(bump(&addr, id), Err(UmiError::Remote(ErrorCode::ResourcePoisoned, _))));
+        // other resources and the endpoint itself are unaffected
+        assert!(bump(&addr, other).is_ok());
+        assert!(call_ping(&addr));
+    }
+
+    #[test]
+    fn panicking_method_keeps_its_resource_by_policy() {
+        let addr = start_counter_server(PanicPolicy::Keep);
+        let id = new_counter(&addr);
+        assert!(bump(&addr, id).is_ok());
+        assert!(matches!(bump(&addr, id), Err(UmiError::RemotePanic(_))));
+        assert!(matches!(bump(&addr, id), Err(UmiError::RemotePanic(_))));
+        assert!(call_ping(&addr));
+    }
+
+    #[test]
+    fn failed_calls_keep_the_resources_moved_into_them() {
+        let addr = start_counter_server(PanicPolicy::Poison);
+        let id = new_counter(&addr);
+        let consume = |fail: Variable| {
+            let args = vec![Variable::OwnedRemote(vec![], addr.clone(), id), fail];
+            invoke(&addr, Message::Invoke(fn_type_name(&counter_consume).to_string(), args, InvokeOp::Owned))
+        };
+        assert!(matches!(consume(Variable::OwnedLocal(encode(&"yes").unwrap())), Err(UmiError::Remote(ErrorCode::ArgumentMismatch, _))));
+        assert!(matches!(consume(Variable::OwnedLocal(encode(&true).unwrap())), Err(UmiError::RemotePanic(_))));
+        assert!(bump(&addr, id).is_ok());
+
+        assert!(consume(Variable::OwnedLocal(encode(&false).unwrap())).is_ok());
+        assert!(matches!(bump(&addr, id), Err(UmiError::Remote(ErrorCode::UnknownResource, _))));
+    }
+
+    #[test]
+    fn methods_call_back_into_their_endpoint() {
+        let addr = start_counter_server(PanicPolicy::Poison);
+        // nested deeper than the pool has workers
+        let args = vec![Variable::OwnedLocal(encode(&addr).unwrap()), Variable::OwnedLocal(encode(&8u32).unwrap())];
+        let msg = Message::Invoke(fn_type_name(&chain).to_string(), args, InvokeOp::Owned);
+        match invoke(&addr, msg).unwrap() {
+            ReturnVar::Owned(s) => assert!(decode::<bool>(&s).unwrap()),
+            var => panic!("Unexpected return {:?}", var)
+        }
+
+        let (a, b) = (new_counter(&addr), new_counter(&addr));
+        assert_eq!(relay(&addr, a, b), Ok(()));
+        // a is borrowed mutably by the caller of the nested request
+        assert!(matches!(relay(&addr, a, a), Err(UmiError::Remote(ErrorCode::Deadlock, _))));
+        assert!(bump(&addr, a).is_ok());
+    }
+
+    #[test]
+    fn functions_take_any_number_of_arguments() {
+        let addr = start_counter_server(PanicPolicy::Poison);
+        let (target, source) = (new_counter(&addr), new_counter(&addr));
+        let mut args = vec![Variable::MutRefRemote(vec![], addr.clone(), target), Variable::RefRemote(vec![], addr.clone(), source)];
+        args.extend([1u32, 2, 3].iter().map(|x| Variable::OwnedLocal(encode(x).unwrap())));
+        args.push(Variable::OwnedLocal(encode(&"total".to_string()).unwrap()));
+        let msg = Message::Invoke(fn_type_name(&counter_mix).to_string(), args, InvokeOp::Owned);
+        match invoke(&addr, msg).unwrap() {
+            ReturnVar::Owned(s) => assert_eq!(decode::<Option<String>>(&s).unwrap(), Some("total 6".to_string())),
+            var => panic!("Unexpected return {:?}", var)
+        }
+        // the target was mutated in place, so a bump overflows it
+        assert!(matches!(bump(&addr, target), Err(UmiError::RemotePanic(_))));
+    }
+
+    #[test]
+    fn resources_are_local_to_their_node_whatever_its_address() {
+        let mut server = UMIEndpoint::new("0.0.0.0:0");
+        let bound = server.local_address();
+        let port = match bound {
+            Address::Tcp(addr) => addr.port(),
+            ref addr => panic!("Unexpected address {}", addr)
+        };
+        server.advertise(format!("127.0.0.1:{}", port));
+        let node = serve(counter_table(), server);
+        assert_eq!(node.addrs, vec![Address::Tcp(([127, 0, 0, 1], port).into())]);
+        assert!(bump(&node, new_counter(&node)).is_ok());
+
+        // the same endpoint reached at the address it is bound to
+        let alias = Node::new(node.id, vec![bound]);
+        assert!(bump(&alias, new_counter(&node)).is_ok());
+        // another endpoint that happens to have the same address, its resource is sent as a proxy
+        let stranger = Node::new(random_node_id(), node.addrs.clone());
+        assert!(matches!(bump(&stranger, new_counter(&node)), Err(UmiError::Remote(ErrorCode::ArgumentMismatch, _))));
+    }
+
+    #[test]
+    fn fallible_constructors_store_their_value() {
+        let vtable = Arc::new(ResourceTable::new());
+        let node = serve_with(counter_table(), UMIEndpoint::new("127.0.0.1:0"), Arc::clone(&vtable));
+        let open = |start: u32| {
+            let msg = Message::Invoke(fn_type_name(&counter_open).to_string(), vec![Variable::OwnedLocal(encode(&start).unwrap())], InvokeOp::Init);
+            invoke(&node, msg)
+        };
+        let id = match open(0) {
+            Ok(ReturnVar::OwnedInit(_, id, true)) => id,
+            var => panic!("Unexpected return {:?}", var)
+        };
+        assert!(bump(&node, id).is_ok());
+        assert!(matches!(open(10), Err(UmiError::Remote(ErrorCode::InitFailed, _))));
+        assert_eq!(vtable.len(), 1);
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::io;
+use serde::{Serialize, Deserialize};
+use crate::codec::CodecError;
+use crate::message_serialisation::ErrorCode;
+
+/* Why a remote call failed. Errors can travel between endpoints, so they only carry descriptions */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum UmiError {
+    Connect(String), // the endpoint could not be resolved or reached
+    Io(String), // the connection failed while a request was in flight
+    Decode(String), // a message or a value could not be decoded
+    UnknownFunction(String), // the endpoint has no function registered under this name
+    RemotePanic(String), // the invoked function panicked on the endpoint
+    Timeout(String), // no response within the request timeout
+    InvalidResponse(String), // the endpoint answered with an unexpected message
+    Remote(ErrorCode, String), // the endpoint refused the request, see ErrorCode
+}
+
+impl UmiError {
+    /* The error for a Message::Error answered by an endpoint */
+    pub fn from_remote(code: ErrorCode, detail: String) -> UmiError {
+        match code {
+            ErrorCode::UnknownFunction => UmiError::UnknownFunction(detail),
+            ErrorCode::RemotePanic => UmiError::RemotePanic(detail),
+            _ => UmiError::Remote(code, detail)
+        }
+    }
+}
+
+impl fmt::Display for UmiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UmiError::Connect(e) => write!(f, "unable to connect: {}", e),
+            UmiError::Io(e) => write!(f, "io error: {}", e),
+            UmiError::Decode(e) => write!(f, "unable to decode: {}", e),
+            UmiError::UnknownFunction(name) => write!(f, "no such function: {}", name),
+            UmiError::RemotePanic(e) => write!(f, "remote panic: {}", e),
+            UmiError::Timeout(e) => write!(f, "timed out: {}", e),
+            UmiError::InvalidResponse(e) => write!(f, "invalid response: {}", e),
+            UmiError::Remote(code, detail) => write!(f, "request refused ({:?}): {}", code, detail),
+        }
+    }
+}
+
+impl std::error::Error for UmiError {}
+
+impl From<CodecError> for UmiError {
+    fn from(e: CodecError) -> Self {
+        UmiError::Decode(e.0)
+    }
+}
+
+impl From<io::Error> for UmiError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            // read and write timeouts surface as WouldBlock on unix and TimedOut on windows
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => UmiError::Timeout(e.to_string()),
+            _ => UmiError::Io(e.to_string())
+        }
+    }
+}
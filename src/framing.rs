@@ -0,0 +1,191 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/* Every message on the wire is a frame:
+ * | magic (2) | version (1) | flags (1) | payload length (4, big endian) | payload |
+ */
+pub const MAGIC: [u8; 2] = *b"UM";
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 8;
+pub const DEFAULT_MAX_PAYLOAD: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub flags: u8,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Closed, // the peer closed the connection between two frames
+    Io(io::Error),
+    BadMagic([u8; 2]),
+    UnsupportedVersion(u8),
+    TooLarge(usize, usize), // (payload length, maximum)
+    Truncated(usize, usize), // (bytes expected, bytes received)
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Closed => write!(f, "connection closed"),
+            FrameError::Io(e) => write!(f, "io error: {}", e),
+            FrameError::BadMagic(m) => write!(f, "bad frame magic: {:?}", m),
+            FrameError::UnsupportedVersion(v) => write!(f, "unsupported frame version: {}", v),
+            FrameError::TooLarge(len, max) => write!(f, "frame payload of {} bytes exceeds the maximum of {} bytes", len, max),
+            FrameError::Truncated(expected, received) => write!(f, "truncated frame: expected {} bytes, received {}", expected, received),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+pub fn encode_frame(flags: u8, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame payload too large"))?;
+    let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+    buf.extend_from_slice(&MAGIC);
+    buf.push(VERSION);
+    buf.push(flags);
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(payload);
+    Ok(buf)
+}
+
+pub fn write_frame<W: Write + ?Sized>(writer: &mut W, flags: u8, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&encode_frame(flags, payload)?)?;
+    writer.flush()
+}
+
+/* Validates headers and enforces a maximum payload size before anything is allocated */
+#[derive(Debug, Clone, Copy)]
+pub struct FrameDecoder {
+    max_payload: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        FrameDecoder::new(DEFAULT_MAX_PAYLOAD)
+    }
+}
+
+impl FrameDecoder {
+    pub fn new(max_payload: usize) -> FrameDecoder {
+        FrameDecoder { max_payload }
+    }
+
+    // returns the flags and payload length of a complete header
+    fn check_header(&self, header: &[u8]) -> Result<(u8, usize), FrameError> {
+        if header[0..2] != MAGIC {
+            return Err(FrameError::BadMagic([header[0], header[1]]));
+        }
+        if header[2] != VERSION {
+            return Err(FrameError::UnsupportedVersion(header[2]));
+        }
+        let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if len > self.max_payload {
+            return Err(FrameError::TooLarge(len, self.max_payload));
+        }
+        Ok((header[3], len))
+    }
+
+    /* Decodes a frame from the start of a buffer.
+     * Ok(None) means more bytes are needed, otherwise the frame and the number of bytes it used are returned.
+     */
+    pub fn decode(&self, buf: &[u8]) -> Result<Option<(Frame, usize)>, FrameError> {
+        if buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let (flags, len) = self.check_header(&buf[..HEADER_LEN])?;
+        if buf.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        let payload = buf[HEADER_LEN..HEADER_LEN + len].to_vec();
+        Ok(Some((Frame { flags, payload }, HEADER_LEN + len)))
+    }
+
+    /* Reads exactly one frame from a stream.
+     * A stream ending before the first header byte is Closed, ending anywhere else is Truncated.
+     */
+    pub fn read_frame<R: Read + ?Sized>(&self, reader: &mut R) -> Result<Frame, FrameError> {
+        let mut header = [0u8; HEADER_LEN];
+        match read_full(reader, &mut header)? {
+            0 => return Err(FrameError::Closed),
+            HEADER_LEN => {},
+            n => return Err(FrameError::Truncated(HEADER_LEN, n))
+        }
+        let (flags, len) = self.check_header(&header)?;
+        let mut payload = vec![0u8; len];
+        let n = read_full(reader, &mut payload)?;
+        if n < len {
+            return Err(FrameError::Truncated(HEADER_LEN + len, HEADER_LEN + n));
+        }
+        Ok(Frame { flags, payload })
+    }
+}
+
+// like read_exact, but reports how many bytes were read before the end of the stream
+fn read_full<R: Read + ?Sized>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frame_round_trip() {
+        let payload = b"line one\nline two\0binary".to_vec();
+        let buf = encode_frame(3, &payload).unwrap();
+        let frame = FrameDecoder::default().read_frame(&mut &buf[..]).unwrap();
+        assert_eq!(frame, Frame { flags: 3, payload });
+    }
+
+    #[test]
+    fn rejects_oversized_frame() {
+        let buf = encode_frame(0, &[0u8; 64]).unwrap();
+        match FrameDecoder::new(16).read_frame(&mut &buf[..]) {
+            Err(FrameError::TooLarge(64, 16)) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_frame() {
+        let buf = encode_frame(0, b"hello").unwrap();
+        match FrameDecoder::default().read_frame(&mut &buf[..buf.len() - 2]) {
+            Err(FrameError::Truncated(13, 11)) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        match FrameDecoder::default().read_frame(&mut &buf[..3]) {
+            Err(FrameError::Truncated(HEADER_LEN, 3)) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        assert!(FrameDecoder::default().decode(&buf[..buf.len() - 1]).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut buf = encode_frame(0, b"hello").unwrap();
+        buf[2] = VERSION + 1;
+        assert!(matches!(FrameDecoder::default().decode(&buf), Err(FrameError::UnsupportedVersion(_))));
+        buf[0] = b'{';
+        assert!(matches!(FrameDecoder::default().read_frame(&mut &buf[..]), Err(FrameError::BadMagic(_))));
+        assert!(matches!(FrameDecoder::default().read_frame(&mut &[][..]), Err(FrameError::Closed)));
+    }
+}
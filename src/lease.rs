@@ -0,0 +1,289 @@
+use std::sync::{Arc, Weak, Mutex, Condvar, Once};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::codec::{CodecKind, decode, current_codec, with_codec};
+use crate::tls::{TlsClientConfig, client_config, with_client_config};
+use crate::error::UmiError;
+use crate::message_serialisation::{Message, ReturnVar, invoke};
+
+use umi_macros::{ID, Node};
+
+/* The leases on the resources owned by this process, renewed in the background.
+ * An endpoint removes a resource created by a remote initialisation once its lease expires,
+ * so that the resources of a crashed or killed client do not stay on the endpoint forever.
+ */
+
+// a lease is renewed with the codec and the TLS configuration of the thread that took it
+struct Lease {
+    node: Node,
+    id: ID,
+    owner: Weak<AtomicBool>, // the flag shared by an owning proxy and its clones
+    codec: CodecKind,
+    tls: Option<TlsClientConfig>,
+}
+
+impl Lease {
+    fn is_alive(&self) -> bool {
+        self.owner.upgrade().is_some_and(|owner| owner.load(Ordering::Relaxed))
+    }
+}
+
+static HELD: Mutex<Vec<Lease>> = Mutex::new(Vec::new());
+static RENEW_INTERVAL: Mutex<Duration> = Mutex::new(Duration::from_secs(10));
+static INTERVAL_CHANGED: Condvar = Condvar::new();
+static RENEWER: Once = Once::new();
+
+/* Sets how often leases are renewed, it has to be shorter than the lease of the endpoints.
+ * It applies at once, also to a renewer already waiting for its next round
+ */
+pub fn set_renew_interval(interval: Duration) {
+    *RENEW_INTERVAL.lock().unwrap() = interval;
+    INTERVAL_CHANGED.notify_all();
+}
+
+pub fn renew_interval() -> Duration {
+    *RENEW_INTERVAL.lock().unwrap()
+}
+
+/* Renews the lease of a resource for as long as the proxy owning it, or one of its clones, is alive and still the owner */
+pub fn hold_lease(node: &Node, id: ID, owner: &Arc<AtomicBool>) {
+    HELD.lock().unwrap().push(Lease {
+        node: node.clone(),
+        id,
+        owner: Arc::downgrade(owner),
+        codec: current_codec(),
+        tls: client_config(),
+    });
+    RENEWER.call_once(|| {
+        thread::spawn(renew_periodically);
+    });
+}
+
+// renews every lease each interval after the previous round, waking up early when the interval changes
+fn renew_periodically() {
+    let mut last = Instant::now();
+    loop {
+        let mut interval = RENEW_INTERVAL.lock().unwrap();
+        let mut now = Instant::now();
+        while now < last + *interval {
+            let left = last + *interval - now;
+            interval = INTERVAL_CHANGED.wait_timeout(interval, left).unwrap().0;
+            now = Instant::now();
+        }
+        drop(interval);
+        renew_all();
+        last = Instant::now();
+    }
+}
+
+/* Renews the leases of ids on the endpoint node, returns the ids the endpoint does not hold anymore */
+pub fn renew(node: &Node, ids: Vec<ID>) -> Result<Vec<ID>, UmiError> {
+    match invoke(node, Message::Renew(ids))? {
+        ReturnVar::Owned(s) => Ok(decode(&s)?),
+        var => Err(UmiError::InvalidResponse(format!("{:?}", var)))
+    }
+}
+
+// the leases on an endpoint taken with the same settings, renewed together
+struct Batch {
+    node: Node,
+    codec: CodecKind,
+    tls: Option<TlsClientConfig>,
+    ids: Vec<ID>,
+}
+
+impl Batch {
+    fn includes(&self, lease: &Lease) -> bool {
+        self.node == lease.node && self.codec == lease.codec
+            && self.tls.as_ref().map(|c| c.id()) == lease.tls.as_ref().map(|c| c.id())
+    }
+}
+
+fn renew_all() {
+    let mut batches: Vec<Batch> = Vec::new();
+    {
+        let mut held = HELD.lock().unwrap();
+        held.retain(|lease| lease.is_alive());
+        for lease in held.iter() {
+            match batches.iter_mut().find(|batch| batch.includes(lease)) {
+                Some(batch) => batch.ids.push(lease.id),
+                None => batches.push(Batch { node: lease.node.clone(), codec: lease.codec, tls: lease.tls.clone(), ids: vec![lease.id] })
+            }
+        }
+    }
+    for Batch { node, codec, tls, ids } in batches {
+        let renewed = with_codec(codec, || match tls {
+            Some(tls) => with_client_config(tls, || renew(&node, ids)),
+            None => renew(&node, ids)
+        });
+        match renewed {
+            Ok(unknown) => {
+                if !unknown.is_empty() {
+                    println!("Resources {:?} are gone from {}", unknown, node);
+                    HELD.lock().unwrap().retain(|lease| lease.node != node || !unknown.contains(&lease.id));
+                }
+            },
+            Err(e) => println!("Failed to renew leases on {}: {}", node, e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::endpoint::{UMIEndpoint, ResourceTable};
+    use serde::{Serialize, Deserialize};
+    use crate::message_serialisation::{Message, ReturnVar, InvokeOp, invoke};
+    use crate::codec::encode;
+    use crate::registry::*;
+    use crate::proxy_lib::*;
+    use crate::utils::{fn_type_name};
+    use crate::codec::{CodecKind, with_codec};
+    use crate::tls::{TlsServerConfig, TlsClientConfig, with_client_config};
+    use umi_macros::{Address, Node};
+    use crate::register;
+    use std::any::Any;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::process::{Child, Command, Stdio};
+
+    // a resource whose method panics on its second call
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Counter(u32);
+
+    impl BorrowRemote for Counter {
+        fn borrow_remote(&self) -> Self {
+            panic!("This should never be called");
+        }
+    }
+
+    impl SerializeTagL for Counter {
+        fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+            (encode(self).unwrap(), true)
+        }
+    }
+
+    fn counter_new() -> Counter {
+        Counter(0)
+    }
+
+    fn counter_table() -> RegistryTable {
+        let mut table = RegistryTable::new();
+        register!(table, CounterNew, counter_new, fn() -> Counter, (Counter, ResultOp::Owned));
+        table
+    }
+
+    fn serve_with(table: RegistryTable, mut server: UMIEndpoint, vtable: Arc<ResourceTable>) -> Node {
+        let node = server.node();
+        thread::spawn(move || server.start(table, vtable));
+        node
+    }
+
+    // a CA with a certificate for the endpoint and one for a client, as (ca, server cert, server key, client cert, client key)
+    fn certificates() -> (String, String, String, String, String) {
+        use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let server_key = KeyPair::generate().unwrap();
+        let server = CertificateParams::new(vec!["localhost".to_string()]).unwrap()
+            .signed_by(&server_key, &ca, &ca_key).unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let mut client_params = CertificateParams::new(vec!["client".to_string()]).unwrap();
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+        (ca.pem(), server.pem(), server_key.serialize_pem(), client.pem(), client_key.serialize_pem())
+    }
+
+    // run in a child process by leases_of_a_killed_client_expire, it owns a counter until it is killed
+    #[test]
+    #[ignore]
+    fn lease_holder() {
+        let addr: Address = match std::env::var("UMI_LEASE_ENDPOINT") {
+            Ok(addr) => addr.parse().unwrap(),
+            Err(_) => return
+        };
+        set_renew_interval(Duration::from_millis(50));
+        let msg = Message::Invoke(fn_type_name(&counter_new).to_string(), vec![], InvokeOp::Init);
+        let (node, id) = match invoke(&addr, msg).unwrap() {
+            ReturnVar::OwnedInit(node, id, _) => (node, id),
+            var => panic!("Unexpected return {:?}", var)
+        };
+        let owner = Arc::new(AtomicBool::new(true));
+        hold_lease(&node, id, &owner);
+        loop {
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    fn wait_until<F: Fn() -> bool>(condition: F, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn leases_of_a_killed_client_expire() {
+        let mut table = RegistryTable::new();
+        register!(table, CounterNew, counter_new, fn() -> Counter, (Counter, ResultOp::Owned));
+        let mut server = UMIEndpoint::new("127.0.0.1:0");
+        server.set_lease(Some(Duration::from_millis(300)));
+        let vtable = Arc::new(ResourceTable::new());
+        let addr = serve_with(table, server, Arc::clone(&vtable));
+
+        // killed on drop too, so that a failing test does not leave the client running
+        struct Client(Child);
+        impl Drop for Client {
+            fn drop(&mut self) {
+                let _ = self.0.kill();
+                let _ = self.0.wait();
+            }
+        }
+        let client = Client(Command::new(std::env::current_exe().unwrap())
+            .args(["lease::test::lease_holder", "--exact", "--ignored", "--nocapture"])
+            .env("UMI_LEASE_ENDPOINT", addr.addrs[0].to_string())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap());
+        wait_until(|| vtable.len() == 1, Duration::from_secs(10));
+        // the client renews the lease well past its length
+        thread::sleep(Duration::from_secs(1));
+        assert_eq!(vtable.len(), 1);
+
+        drop(client);
+        wait_until(|| vtable.is_empty(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn leases_are_renewed_with_the_settings_they_were_taken_with() {
+        let (ca, cert, key, _, _) = certificates();
+        // applies to the renewer even when another lease started it with a longer interval
+        set_renew_interval(Duration::from_millis(50));
+        let mut server = UMIEndpoint::with_tls("tls:localhost@127.0.0.1:0", TlsServerConfig::new(cert.as_bytes(), key.as_bytes()).unwrap());
+        server.set_lease(Some(Duration::from_millis(300)));
+        let vtable = Arc::new(ResourceTable::new());
+        let node = serve_with(counter_table(), server, Arc::clone(&vtable));
+
+        // only this thread is configured for TLS
+        let owner = Arc::new(AtomicBool::new(true));
+        let client = TlsClientConfig::new(ca.as_bytes()).unwrap();
+        with_client_config(client, || with_codec(CodecKind::Bincode, || {
+            let msg = Message::Invoke(fn_type_name(&counter_new).to_string(), vec![], InvokeOp::Init);
+            match invoke(&node, msg).unwrap() {
+                ReturnVar::OwnedInit(node, id, _) => hold_lease(&node, id, &owner),
+                var => panic!("Unexpected return {:?}", var)
+            }
+        }));
+        thread::sleep(Duration::from_secs(1));
+        assert_eq!(vtable.len(), 1);
+
+        owner.store(false, Ordering::Relaxed);
+        wait_until(|| vtable.is_empty(), Duration::from_secs(5));
+    }
+}
@@ -1,6 +1,14 @@
 #![allow(unused)]
+pub mod codec;
 pub mod endpoint;
+pub mod error;
+pub mod framing;
+pub mod lease;
 pub mod message_serialisation;
+pub mod naming;
 pub mod registry;
+pub mod resource_table;
+pub mod tls;
+pub mod transport;
 pub mod utils;
 pub mod proxy_lib;
\ No newline at end of file
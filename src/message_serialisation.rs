@@ -1,22 +1,31 @@
 use serde::{Serialize, Deserialize};
-use std::net::{TcpStream, ToSocketAddrs, Shutdown, SocketAddr};
-use std::io::{Read, Write, BufReader, BufRead, BufWriter};
+use std::io::{self, Read, Write};
+use std::collections::{HashMap};
+use std::sync::{Mutex, OnceLock};
+use std::cell::Cell;
+use std::time::Duration;
 use std::any::{TypeId};
 use std::fmt::{Debug};
 use crate::registry::{RegistryTable};
+use crate::codec::{CodecKind, current_codec, decode};
+use crate::error::UmiError;
+use crate::framing::{write_frame, FrameDecoder, FrameError};
+use crate::transport::{Connection, ToAddress, connect};
+use crate::tls::{client_config_id};
+use crate::endpoint::{dispatch_local};
 use crate::utils::{fn_type_name};
 
-use umi_macros::{IsLocal, ToVariable, ToVariableRef, ToVariableMut, Variable, ID};
+use umi_macros::{IsLocal, ToVariable, ToVariableRef, ToVariableMut, Variable, ID, Address, Node};
 
 /* The variable representing a return */
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ReturnVar {
-    Owned(String), // either local or remote
-    OwnedInit(SocketAddr, ID, bool), // has to be a proxy, i.e., remote
-    RefOwned(SocketAddr, ID), // a reference owning a reference on the remote machine
-    RefBorrow(String), // a reference borrowing resource on a remote machine
-    MutRefOwned(SocketAddr, ID), // a mutable reference owning a reference on the remote machine
-    MutRefBorrow(String), // a mutable reference borrowing resource on a remote machine
+    Owned(Vec<u8>), // either local or remote
+    OwnedInit(Node, ID, bool), // has to be a proxy, i.e., remote
+    RefOwned(Node, ID), // a reference owning a reference on the remote machine
+    RefBorrow(Vec<u8>), // a reference borrowing resource on a remote machine
+    MutRefOwned(Node, ID), // a mutable reference owning a reference on the remote machine
+    MutRefBorrow(Vec<u8>), // a mutable reference borrowing resource on a remote machine
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -24,54 +33,385 @@ pub enum InvokeOp {
     Owned, // result pass by copy / move
     Ref, // result pass by reference
     MutRef, // // result pass by mutable reference
-    Init // remote initialisation call
+    Init, // remote initialisation call
+    InitNamed(String) // remote initialisation call, the resource is bound to the name
+}
+
+/* Why an endpoint could not answer a request */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    MalformedMessage, // the request could not be decoded
+    UnsupportedCodec, // the frame names a codec the endpoint does not know
+    UnexpectedMessage, // the message is not a request, e.g. a Return
+    UnknownFunction, // no function is registered under the name
+    UnknownResource, // no resource with the ID lives on the endpoint, or the token of the ID is wrong
+    ResourceBusy, // the resource is borrowed by another request
+    ArgumentMismatch, // the number of arguments, or the type or ownership of one, is not what the function expects
+    RemotePanic, // the function panicked
+    ResourcePoisoned, // a function panicked while mutating the resource
+    Deadlock, // the request waits for resources held by itself or by requests waiting for it, within one endpoint
+    UnknownName, // no resource is bound to the name
+    NameTaken, // another resource is bound to the name
+    InitFailed, // the constructor returned an error
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Message {
     Invoke(String, Vec<Variable>, InvokeOp),  // (function_name, variables, return_option)
     Return(ReturnVar), // return a variable with one of return variable representations
-    Drop(ID) // deallocate remotely owned resource 
-}
-
-pub type BadResponseError = String;
-
-pub fn send<A: ToSocketAddrs>(addr: A, msg: Message) -> Result<String, BadResponseError> {
-    match TcpStream::connect(addr) {
-        Ok(mut stream) => {
-            println!("Successfully connected to server");
-            let stream_clone = stream.try_clone().unwrap();
-            let s_msg = serde_json::to_string(&msg).unwrap();
-            let mut writer = BufWriter::new(stream);
-            writer.write(s_msg.as_bytes()).unwrap();
-            writer.write(b"\n").unwrap();
-            writer.flush().unwrap();
-            println!("Message Sent");
-
-            let mut data = String::new(); // string buffer
-            let mut reader = BufReader::new(stream_clone);
-            match reader.read_line(&mut data) {
-                Ok(_) => {
-                    Ok(data)
+    Drop(ID), // deallocate remotely owned resource 
+    Renew(Vec<ID>), // extend the leases of remotely owned resources, answered with the ids the endpoint does not hold
+    Release(ID), // release a borrow entry returned as ReturnVar::RefOwned or ReturnVar::MutRefOwned
+    Attach(String), // get a proxy of the resource bound to the name, answered with ReturnVar::OwnedInit
+    Names, // list the names bound on the endpoint
+    Error(ErrorCode, String) // the request failed on the endpoint, (code, detail)
+}
+
+impl Message {
+    /* Whether the endpoint may receive the message twice with the same effect, so that it can be sent again */
+    pub fn is_idempotent(&self) -> bool {
+        matches!(self, Message::Renew(_) | Message::Attach(_) | Message::Names)
+    }
+}
+
+/* A failure while serving a request, answered with Message::Error */
+pub type CallError = (ErrorCode, String);
+
+pub type BadResponseError = UmiError;
+
+/* Idle connections are kept per server address so that proxies reuse sockets between calls.
+ * A TLS connection is only reused with the client configuration it was opened with
+ */
+pub struct ConnectionPool {
+    idle: Mutex<HashMap<PoolKey, Vec<Box<dyn Connection>>>>,
+    max_idle: usize, // per address and configuration
+}
+
+// the address with the id of the TLS client configuration a connection to it was opened with
+type PoolKey = (Address, Option<u64>);
+
+fn pool_key(addr: &Address) -> PoolKey {
+    match addr {
+        Address::Tls(..) => (addr.clone(), client_config_id()),
+        _ => (addr.clone(), None)
+    }
+}
+
+impl ConnectionPool {
+    pub fn new(max_idle: usize) -> ConnectionPool {
+        ConnectionPool {
+            idle: Mutex::new(HashMap::new()),
+            max_idle,
+        }
+    }
+
+    pub fn checkout(&self, addr: &Address) -> Option<Box<dyn Connection>> {
+        self.idle.lock().unwrap().get_mut(&pool_key(addr)).and_then(|conns| conns.pop())
+    }
+
+    pub fn checkin(&self, addr: Address, conn: Box<dyn Connection>) {
+        let mut idle = self.idle.lock().unwrap();
+        let conns = idle.entry(pool_key(&addr)).or_default();
+        if conns.len() < self.max_idle {
+            conns.push(conn);
+        } // otherwise the connection is dropped and closed
+    }
+
+    pub fn idle_count(&self, addr: &Address) -> usize {
+        self.idle.lock().unwrap().get(&pool_key(addr)).map_or(0, |conns| conns.len())
+    }
+
+    pub fn clear(&self) {
+        self.idle.lock().unwrap().clear();
+    }
+}
+
+static POOL: OnceLock<ConnectionPool> = OnceLock::new();
+
+/* The process-wide pool used by send() */
+pub fn connection_pool() -> &'static ConnectionPool {
+    POOL.get_or_init(|| ConnectionPool::new(8))
+}
+
+static REQUEST_TIMEOUT: Mutex<Option<Duration>> = Mutex::new(None);
+
+thread_local! {
+    static CURRENT_REQUEST_TIMEOUT: Cell<Option<Option<Duration>>> = const { Cell::new(None) };
+}
+
+/* Sets how long a proxy waits for a response before failing with UmiError::Timeout, None waits forever */
+pub fn set_request_timeout(timeout: Option<Duration>) {
+    *REQUEST_TIMEOUT.lock().unwrap() = timeout;
+}
+
+/* Runs f with timeout as the request timeout of the current thread */
+pub fn with_request_timeout<R, F: FnOnce() -> R>(timeout: Option<Duration>, f: F) -> R {
+    let previous = CURRENT_REQUEST_TIMEOUT.with(|t| t.replace(Some(timeout)));
+    struct Restore(Option<Option<Duration>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT_REQUEST_TIMEOUT.with(|t| t.set(self.0));
+        }
+    }
+    let _restore = Restore(previous);
+    f()
+}
+
+pub fn request_timeout() -> Option<Duration> {
+    CURRENT_REQUEST_TIMEOUT.with(|t| t.get())
+        .unwrap_or_else(|| *REQUEST_TIMEOUT.lock().unwrap())
+}
+
+/* Sends a message encoded with the current codec and returns the encoded reply */
+pub fn send<A: ToAddress>(addr: A, msg: Message) -> Result<Vec<u8>, UmiError> {
+    let codec = current_codec();
+    let timeout = request_timeout();
+    let addrs = match addr.to_addresses() {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            println!("Failed to resolve address: {}", e);
+            return Err(UmiError::Connect(e.to_string()));
+        }
+    };
+    // a method calling back into the endpoint serving it is executed in place
+    let msg = match dispatch_local(addr.node_id(), &addrs, msg) {
+        Ok(reply) => return Ok(codec.encode(&reply)?),
+        Err(msg) => msg
+    };
+    let s_msg = codec.encode(&msg)?;
+    let pool = connection_pool();
+    let mut last_error = UmiError::Connect("no address to connect to".to_string());
+    for addr in addrs {
+        // an idle connection the server closed is dropped before anything is sent on it
+        if let Some(mut conn) = pool.checkout(&addr).filter(|conn| conn.is_open()) {
+            match exchange(&mut *conn, codec, timeout, &s_msg) {
+                Ok(data) => {
+                    pool.checkin(addr, conn);
+                    return Ok(data);
                 },
-                Err(e) => {
-                    println!("Failed to receive data: {}", e);
-                    Err("Uable to receive data from server".to_string())
+                // the request may still be executing, so it must not be sent again
+                Err((e @ UmiError::Timeout(_), true)) => return Err(e),
+                // the server may have executed a request it received in full
+                Err((e, true)) if !msg.is_idempotent() => return Err(e),
+                // a request not written in full cannot have been executed, e.g. the server restarted
+                // since the connection was pooled, it is sent again on a fresh connection
+                Err(_) => {}
+            }
+        }
+        match connect(&addr) {
+            Ok(mut conn) => {
+                println!("Successfully connected to server");
+                match exchange(&mut *conn, codec, timeout, &s_msg) {
+                    Ok(data) => {
+                        pool.checkin(addr, conn);
+                        return Ok(data);
+                    },
+                    Err((e, _)) => {
+                        println!("Failed to receive data: {}", e);
+                        return Err(e);
+                    }
                 }
+            },
+            Err(e) => {
+                println!("Failed to connect: {}", e);
+                last_error = UmiError::Connect(e.to_string());
             }
-        },
-        Err(e) => {
-            println!("Failed to connect: {}", e);
-            Err("Connection Failure".to_string())
         }
     }
+    Err(last_error)
+}
+
+/* Sends a message and decodes the variable returned by the endpoint */
+pub fn invoke<A: ToAddress>(addr: A, msg: Message) -> Result<ReturnVar, UmiError> {
+    let reply: Message = decode(&send(addr, msg)?)?;
+    match reply {
+        Message::Return(var) => Ok(var),
+        Message::Error(code, detail) => Err(UmiError::from_remote(code, detail)),
+        other => Err(UmiError::InvalidResponse(format!("expected a return message, got {:?}", other)))
+    }
+}
+
+// writes one request frame and reads back one response frame on the same connection,
+// a failure tells whether the request was written in full
+fn exchange(conn: &mut dyn Connection, codec: CodecKind, timeout: Option<Duration>, s_msg: &[u8]) -> Result<Vec<u8>, (UmiError, bool)> {
+    conn.set_timeout(timeout).map_err(|e| (e.into(), false))?;
+    write_frame(conn, codec.id(), s_msg).map_err(|e| (e.into(), false))?;
+    println!("Message Sent");
+
+    match FrameDecoder::default().read_frame(conn) {
+        Ok(frame) => Ok(frame.payload),
+        Err(FrameError::Io(e)) => Err((e.into(), true)),
+        Err(FrameError::Closed) => Err((UmiError::Io("connection closed by server".to_string()), true)),
+        Err(e) => Err((UmiError::Decode(e.to_string()), true))
+    }
 }
 
-pub fn response(mut stream: TcpStream, result: Message) {
-    let stream_clone = stream.try_clone().unwrap();
-    let mut writer = BufWriter::new(stream_clone);
-    let response_data: String = serde_json::to_string(&result).unwrap();
-    writer.write(response_data.as_bytes()).unwrap();
-    writer.write(b"\n").unwrap();
-    writer.flush().unwrap();
-}
\ No newline at end of file
+pub fn response<W: Write + ?Sized>(conn: &mut W, codec: CodecKind, result: Message) -> io::Result<()> {
+    let response_data = codec.encode(&result).unwrap();
+    write_frame(conn, codec.id(), &response_data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::endpoint::{UMIEndpoint, ResourceTable};
+    use crate::framing::FrameDecoder;
+    use crate::error::UmiError;
+    use crate::registry::*;
+    use crate::proxy_lib::*;
+    use crate::utils::{fn_type_name};
+    use crate::codec::decode;
+    use crate::transport::{ToAddress};
+    use umi_macros::{Address, Node};
+    use crate::register;
+    use std::any::Any;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn ping() -> bool {
+        true
+    }
+
+    fn start_server<A: ToAddress>(addr: A) -> Node {
+        let mut table = RegistryTable::new();
+        register!(table, Ping, ping, fn() -> bool, (bool, ResultOp::Owned));
+        serve(table, UMIEndpoint::new(addr))
+    }
+
+    fn serve(table: RegistryTable, server: UMIEndpoint) -> Node {
+        serve_with(table, server, Arc::new(ResourceTable::new()))
+    }
+
+    fn serve_with(table: RegistryTable, mut server: UMIEndpoint, vtable: Arc<ResourceTable>) -> Node {
+        let node = server.node();
+        thread::spawn(move || server.start(table, vtable));
+        node
+    }
+
+    fn call_ping<A: ToAddress>(addr: A) -> bool {
+        let msg = Message::Invoke(fn_type_name(&ping).to_string(), vec![], InvokeOp::Owned);
+        match decode(&send(addr, msg).unwrap()).unwrap() {
+            Message::Return(ReturnVar::Owned(s)) => decode::<bool>(&s).unwrap(),
+            _ => panic!("Expecting a return message")
+        }
+    }
+
+    #[test]
+    fn connection_is_reused_across_calls() {
+        let addr = start_server("127.0.0.1:0");
+        for _ in 0..10 {
+            assert!(call_ping(&addr));
+        }
+        assert_eq!(connection_pool().idle_count(&addr.addrs[0]), 1);
+    }
+
+    #[test]
+    fn requests_received_by_the_server_are_not_sent_again() {
+        // reads one request on each connection and closes it without answering
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = Address::Tcp(listener.local_addr().unwrap());
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&received);
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                if FrameDecoder::default().read_frame(&mut stream).is_ok() {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+        let pooled = || connection_pool().checkin(addr.clone(), crate::transport::connect(&addr).unwrap());
+
+        pooled();
+        let msg = Message::Invoke(fn_type_name(&ping).to_string(), vec![], InvokeOp::Owned);
+        assert!(matches!(send(&addr, msg), Err(UmiError::Io(_))));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+
+        // listing names has no effect, so it is sent again on a fresh connection
+        pooled();
+        assert!(send(&addr, Message::Names).is_err());
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+    }
+
+    // a pooled connection the server closed after it was checked, writing to it fails
+    struct Stale(io::ErrorKind);
+
+    impl Read for Stale {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Write for Stale {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(self.0.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Stale {
+        fn shutdown(&self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_nonblocking(&self, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn calls_go_through_after_the_endpoint_restarts() {
+        // the endpoint before its restart answers one request and stops
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = Address::Tcp(listener.local_addr().unwrap());
+        let first = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let frame = FrameDecoder::default().read_frame(&mut stream).unwrap();
+            let codec = CodecKind::from_id(frame.flags).unwrap();
+            let reply = Message::Return(ReturnVar::Owned(codec.encode(&true).unwrap()));
+            response(&mut stream, codec, reply).unwrap();
+        });
+        assert!(call_ping(&addr));
+        first.join().unwrap();
+        assert_eq!(connection_pool().idle_count(&addr), 1);
+
+        start_server(&addr);
+        assert!(call_ping(&addr));
+        assert_eq!(connection_pool().idle_count(&addr), 1);
+
+        // the restart may also close a pooled connection between its check and the write of the request
+        for kind in [io::ErrorKind::BrokenPipe, io::ErrorKind::TimedOut] {
+            connection_pool().checkin(addr.clone(), Box::new(Stale(kind)));
+            assert!(call_ping(&addr));
+        }
+    }
+
+    #[test]
+    fn send_reports_failures_as_errors() {
+        // nothing listens on this address once the listener is dropped
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let msg = Message::Invoke(fn_type_name(&ping).to_string(), vec![], InvokeOp::Owned);
+        assert!(matches!(send(addr, msg), Err(UmiError::Connect(_))));
+
+        // a server that accepts requests but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let _conns: Vec<_> = listener.incoming().collect();
+        });
+        let msg = Message::Invoke(fn_type_name(&ping).to_string(), vec![], InvokeOp::Owned);
+        let result = with_request_timeout(Some(Duration::from_millis(100)), || send(addr, msg));
+        assert!(matches!(result, Err(UmiError::Timeout(_))));
+    }
+}
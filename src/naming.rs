@@ -0,0 +1,115 @@
+use crate::codec::{decode};
+use crate::error::UmiError;
+use crate::message_serialisation::{Message, ReturnVar, invoke};
+use crate::transport::{ToAddress};
+
+/* Resources bound to a name on an endpoint, shared by the clients attaching to them.
+ * A resource is bound with remote_named! or ResourceTable::insert_named, and reached with attach!
+ * Only the server unbinds a name, with ResourceTable::unbind, so that no client can destroy a resource others share
+ */
+
+/* The names bound on the endpoint at addr, sorted */
+pub fn names<A: ToAddress>(addr: A) -> Result<Vec<String>, UmiError> {
+    match invoke(addr, Message::Names)? {
+        ReturnVar::Owned(s) => Ok(decode(&s)?),
+        var => Err(UmiError::InvalidResponse(format!("{:?}", var)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::endpoint::{UMIEndpoint, ResourceTable};
+    use serde::{Serialize, Deserialize};
+    use crate::message_serialisation::{Message, ReturnVar, InvokeOp, ErrorCode, invoke};
+    use crate::codec::encode;
+    use umi_macros::{Variable, ID};
+    use crate::error::UmiError;
+    use crate::registry::*;
+    use crate::proxy_lib::*;
+    use crate::utils::{fn_type_name};
+    use umi_macros::Node;
+    use crate::register;
+    use std::any::Any;
+    use std::sync::Arc;
+    use std::thread;
+
+    // a resource whose method panics on its second call
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Counter(u32);
+
+    impl BorrowRemote for Counter {
+        fn borrow_remote(&self) -> Self {
+            panic!("This should never be called");
+        }
+    }
+
+    impl SerializeTagL for Counter {
+        fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+            (encode(self).unwrap(), true)
+        }
+    }
+
+    fn counter_new() -> Counter {
+        Counter(0)
+    }
+
+    fn counter_bump(counter: &mut Counter) -> bool {
+        counter.0 += 1;
+        if counter.0 > 1 {
+            panic!("counter overflow");
+        }
+        true
+    }
+
+    fn counter_table() -> RegistryTable {
+        let mut table = RegistryTable::new();
+        register!(table, CounterNew, counter_new, fn() -> Counter, (Counter, ResultOp::Owned));
+        register!(table, CounterBump, counter_bump, fn(&mut Counter) -> bool, (bool, ResultOp::Owned), Counter, &mut Counter);
+        table
+    }
+
+    fn bump(addr: &Node, id: ID) -> Result<ReturnVar, UmiError> {
+        let msg = Message::Invoke(fn_type_name(&counter_bump).to_string(), vec![Variable::MutRefRemote(vec![], addr.clone(), id)], InvokeOp::Owned);
+        invoke(addr, msg)
+    }
+
+    fn serve_with(table: RegistryTable, mut server: UMIEndpoint, vtable: Arc<ResourceTable>) -> Node {
+        let node = server.node();
+        thread::spawn(move || server.start(table, vtable));
+        node
+    }
+
+    #[test]
+    fn clients_attach_to_named_resources() {
+        let vtable = Arc::new(ResourceTable::new());
+        vtable.insert_named("shared", Box::new(Counter(0))).unwrap();
+        let node = serve_with(counter_table(), UMIEndpoint::new("127.0.0.1:0"), Arc::clone(&vtable));
+        let attach = |name: &str| match invoke(&node, Message::Attach(name.to_string())) {
+            Ok(ReturnVar::OwnedInit(_, id, is_owner)) => Ok((id, is_owner)),
+            Ok(var) => panic!("Unexpected return {:?}", var),
+            Err(e) => Err(e)
+        };
+
+        let (shared, is_owner) = attach("shared").unwrap();
+        assert!(!is_owner);
+        assert!(bump(&node, shared).is_ok());
+        // proxies do not drop a bound resource
+        assert!(invoke(&node, Message::Drop(shared)).is_ok());
+        assert!(vtable.contains(&shared));
+
+        let init = |name: &str| {
+            let msg = Message::Invoke(fn_type_name(&counter_new).to_string(), vec![], InvokeOp::InitNamed(name.to_string()));
+            invoke(&node, msg)
+        };
+        assert!(matches!(init("created"), Ok(ReturnVar::OwnedInit(_, _, false))));
+        assert!(matches!(init("created"), Err(UmiError::Remote(ErrorCode::NameTaken, _))));
+        assert_eq!(vtable.len(), 2);
+        assert_eq!(names(&node).unwrap(), vec!["created".to_string(), "shared".to_string()]);
+
+        assert!(vtable.unbind("shared").is_some());
+        assert!(matches!(bump(&node, shared), Err(UmiError::Remote(ErrorCode::UnknownResource, _))));
+        assert!(matches!(attach("shared"), Err(UmiError::Remote(ErrorCode::UnknownName, _))));
+        assert!(vtable.unbind("shared").is_none());
+    }
+}
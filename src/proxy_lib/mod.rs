@@ -1,5 +1,6 @@
 use std::time::SystemTime;
 use std::any::Any;
+use std::ops::{Deref, DerefMut};
 use std::net::{SocketAddr};
 use std::sync::{Arc};
 use std::sync::atomic::{AtomicBool}; //, Ordering};
@@ -8,12 +9,20 @@ use serde::{Serialize, Deserialize};
 // use crate::message_serialisation::{send, ReturnVar, Message, InvokeOp};
 // use crate::utils::{fn_type_name};
 
+use crate::codec::{encode};
+use crate::message_serialisation::{Message, invoke};
+
 use umi_macros::*;
 // use umi_macros::{IsLocal, ToVariable, ToVariableRef, ToVariableMut, Variable, ID, IsProxyType, SerializeTag};
 // use umi_macros_proc::{IsLocal, ToVariable, ToVariableRef, ToVariableMut, IsProxyType, SerializeTag};
 
 pub trait BorrowRemote {
     fn borrow_remote(&self) -> Self;
+
+    // a proxy moved to this process owns its resource from now on, other values are kept as they are
+    fn own_remote(self) -> Self where Self: Sized {
+        self
+    }
 }
 
 // ToVariableL, ToVariableRefL, ToVariableMutL are for wrapping library types
@@ -34,25 +43,117 @@ pub trait Retrieve {
 }
 
 pub trait Proxy {
-    fn construct_remote(addr: SocketAddr, id: ID, is_owner: Arc<AtomicBool>) -> Self;
+    fn construct_remote(node: Node, id: ID, is_owner: Arc<AtomicBool>) -> Self;
+}
+
+/* A type given a proxy by proxy_me. The proxy is either a local value of the type or stands for a resource on an endpoint,
+ * Ref and Mut are the proxies of the references returned by proxy methods
+ */
+pub trait Proxied: Sized {
+    type Proxy: Proxy;
+    type Ref<'a>: From<&'a Self> where Self: 'a;
+    type Mut<'a>: From<&'a mut Self> where Self: 'a;
+
+    fn remote_ref<'a>(proxy: Self::Proxy, borrow: Option<RemoteBorrow>) -> Self::Ref<'a>;
+    fn remote_mut<'a>(proxy: Self::Proxy, borrow: Option<RemoteBorrow>) -> Self::Mut<'a>;
+}
+
+/* A value returned by a remote method, a returned proxy takes over the resource from the copy left on the endpoint */
+pub trait Lift {
+    fn lift(self) -> Self;
+}
+
+/* The borrow entry on an endpoint behind a reference into a remote resource, released when dropped */
+pub struct RemoteBorrow {
+    node: Node,
+    id: ID,
+}
+
+impl RemoteBorrow {
+    pub fn new(node: Node, id: ID) -> RemoteBorrow {
+        RemoteBorrow { node, id }
+    }
+}
+
+impl Drop for RemoteBorrow {
+    fn drop(&mut self) {
+        // panicking in drop would abort an unwinding thread, the entry is then removed with its resource
+        if let Err(e) = invoke(&self.node, Message::Release(self.id)) {
+            println!("Failed to release remote borrow: {}", e);
+        }
+    }
+}
+
+/* A reference returned by a method of an enum proxy.
+ * A reference into a remote resource is a borrow entry on the endpoint, released when the guard is dropped
+ */
+pub enum RemoteRef<'a, T> {
+    Local(&'a T), // a reference into a local value
+    Remote(T, Option<RemoteBorrow>), // a proxy of the referenced resource, with the borrow entry to release
+}
+
+impl<'a, T> Deref for RemoteRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            RemoteRef::Local(r) => r,
+            RemoteRef::Remote(proxy, _) => proxy
+        }
+    }
+}
+
+impl<'a, T> From<&'a T> for RemoteRef<'a, T> {
+    fn from(r: &'a T) -> Self {
+        RemoteRef::Local(r)
+    }
+}
+
+/* A mutable reference returned by a method of an enum proxy, see RemoteRef */
+pub enum RemoteMut<'a, T> {
+    Local(&'a mut T),
+    Remote(T, Option<RemoteBorrow>),
+}
+
+impl<'a, T> Deref for RemoteMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            RemoteMut::Local(r) => r,
+            RemoteMut::Remote(proxy, _) => proxy
+        }
+    }
+}
+
+impl<'a, T> DerefMut for RemoteMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            RemoteMut::Local(r) => r,
+            RemoteMut::Remote(proxy, _) => proxy
+        }
+    }
+}
+
+impl<'a, T> From<&'a mut T> for RemoteMut<'a, T> {
+    fn from(r: &'a mut T) -> Self {
+        RemoteMut::Local(r)
+    }
 }
 
 pub trait SerializeTagL {
-    fn tagged_string(&self) -> (String, bool); // bool - is_local
+    fn tagged_bytes(&self) -> (Vec<u8>, bool); // bool - is_local
 }
 
 impl SerializeTagL for () {
-    fn tagged_string(&self) -> (String, bool) {
-        ("Empty".to_string(), true)
+    fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+        (encode(self).unwrap(), true)
     }
 }
 
-static mut REFS: Vec<Box<dyn Any>> = Vec::new(); 
-
 impl ToVariableL for SystemTime {
     fn to_variable(self) -> Variable {
-        let var = Variable::OwnedLocal(serde_json::to_string(&self).unwrap());
-        var
+        Variable::OwnedLocal(encode(&self).unwrap())
     }
 }
 
@@ -61,29 +162,31 @@ impl<T> ToVariableL for Option<T>
     T: Serialize,
 {
     fn to_variable(self) -> Variable {
-        let var = Variable::OwnedLocal(serde_json::to_string(&self).unwrap());
-        var
+        Variable::OwnedLocal(encode(&self).unwrap())
     }
 }
 
 impl ToVariableL for u32 {
     fn to_variable(self) -> Variable {
-        let var = Variable::OwnedLocal(serde_json::to_string(&self).unwrap());
-        var
+        Variable::OwnedLocal(encode(&self).unwrap())
+    }
+}
+
+impl ToVariableL for usize {
+    fn to_variable(self) -> Variable {
+        Variable::OwnedLocal(encode(&self).unwrap())
     }
 }
 
 impl ToVariableL for String {
     fn to_variable(self) -> Variable {
-        let var = Variable::OwnedLocal(serde_json::to_string(&self).unwrap());
-        var
+        Variable::OwnedLocal(encode(&self).unwrap())
     }
 }
 
 impl ToVariableL for bool {
     fn to_variable(self) -> Variable {
-        let var = Variable::OwnedLocal(serde_json::to_string(&self).unwrap());
-        var
+        Variable::OwnedLocal(encode(&self).unwrap())
     }
 }
 
@@ -136,15 +239,48 @@ impl<T> SerializeTagL for Option<T>
 where
 T: Serialize,
 {
-    fn tagged_string(&self) -> (String, bool) {
-        let serialised = serde_json::to_string(&self).unwrap();
+    fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+        let serialised = encode(self).unwrap();
+        (serialised, true)
+    }
+}
+
+impl SerializeTagL for u32 {
+    fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+        let serialised = encode(self).unwrap();
+        (serialised, true)
+    }
+}
+
+impl SerializeTagL for usize {
+    fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+        let serialised = encode(self).unwrap();
+        (serialised, true)
+    }
+}
+
+impl SerializeTagL for String {
+    fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+        let serialised = encode(self).unwrap();
         (serialised, true)
     }
 }
 
 impl SerializeTagL for bool {
-    fn tagged_string(&self) -> (String, bool) {
-        let serialised = serde_json::to_string(&self).unwrap();
+    fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+        let serialised = encode(self).unwrap();
         (serialised, true)
     }
-}
\ No newline at end of file
+}
+
+// the result of a fallible method executed on the endpoint
+impl<T, E> SerializeTagL for Result<T, E>
+where
+T: Serialize,
+E: Serialize,
+{
+    fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+        let serialised = encode(self).unwrap();
+        (serialised, true)
+    }
+}
@@ -6,14 +6,23 @@ use std::fmt::Debug;
 use std::net::{SocketAddr};
 use crate::proxy_lib::{BorrowRemote};
 use crate::utils::{fn_type_name};
+use crate::codec::{decode};
+use crate::message_serialisation::{ErrorCode, CallError};
 
 use umi_macros::{SerializeTag};
 use umi_macros_proc::{SerializeTag};
 
 pub type RegistryTable = HashMap<&'static str, Box<dyn GenCall>>;
 
-/* For storing proxies which are used as a reference or mutable reference */
-static mut REFS: Vec<Box<dyn Any>> = Vec::new(); 
+/* A type whose methods are registered together, implemented by #[umi_register] on its impl block */
+pub trait Registrable {
+    fn register_all(table: &mut RegistryTable);
+}
+
+/* Registers the #[umi_init] and #[umi_struct_method] methods of T */
+pub fn register_all<T: Registrable>(table: &mut RegistryTable) {
+    T::register_all(table)
+}
 
 pub struct MutPtr<T> (pub *mut T);
 unsafe impl<T: Send> Send for MutPtr<T> { }
@@ -23,14 +32,37 @@ pub struct ConstPtr<T> (pub *const T);
 unsafe impl<T: Send> Send for ConstPtr<T> { }
 unsafe impl<T: Sync> Sync for ConstPtr<T> { }
 
+/* The pointer stored for a reference returned by a registered function */
+pub trait AsConstPtr<T> {
+    fn as_const_ptr(&self) -> *const T;
+}
+
+impl<T> AsConstPtr<T> for &T {
+    fn as_const_ptr(&self) -> *const T {
+        *self
+    }
+}
+
+pub trait AsMutPtr<T> {
+    fn as_mut_ptr(&mut self) -> *mut T;
+}
+
+impl<T> AsMutPtr<T> for &mut T {
+    fn as_mut_ptr(&mut self) -> *mut T {
+        &mut **self
+    }
+}
+
 /* An argument that is going to passed into the call() function */
 pub enum Argument<'a> {
-    Serialised(String), // the argument is either a serialised copy or proxy
-    Owned(Box<dyn Any + Send + Sync>), // the argument is owned, removed from the vtable
+    Serialised(Vec<u8>), // the argument is either a serialised copy or proxy
+    Moved(Vec<u8>), // a proxy of a resource on another node moved into the call, which owns the resource from now on
+    Owned(&'a Box<dyn Any + Send + Sync>), // the argument is owned, removed from the vtable once the call succeeded
     Ref(&'a Box<dyn Any + Send + Sync>, bool), // the argument is borrowed, retrieved from the vtable
     MutRef(&'a mut Box<dyn Any + Send + Sync>, bool), // the argument is a mutable borrow, retrived from the vtable
-    RemoteRef(String), // remote relative to the reciever, String is a serialised proxy
-    RemoteMutRef(String) // remote relative to the reciever, String is a serialised proxy
+    RemoteRef(Vec<u8>), // remote relative to the reciever, the bytes are a serialised proxy
+    RemoteMutRef(Vec<u8>), // remote relative to the reciever, the bytes are a serialised proxy
+    Decoded(Box<dyn Any>, bool) // a remote borrow decoded by get_arg, (proxy, is_mutable)
 }
 
 // The wrapper, in order to allow the call() function to call on these argument
@@ -42,108 +74,130 @@ pub enum WrapArg<'a, T> {
 }
 
 impl<'a> Argument<'a> {
-    pub fn get_arg<T: 'static + DeserializeOwned + Clone + BorrowRemote>(&'a mut self) -> WrapArg<'a, T> {
+    pub fn get_arg<T: 'static + DeserializeOwned + Clone + BorrowRemote>(&'a mut self) -> Result<WrapArg<'a, T>, CallError> {
+        // the proxy of a remote borrow is kept in the argument, so that it is freed when the call returns
+        if let Argument::RemoteRef(s) | Argument::RemoteMutRef(s) = &*self {
+            let deserialised: T = decode(s).map_err(|e| mismatch::<T>(&e.0))?;
+            let borrow: T = deserialised.borrow_remote();
+            let is_mutable = matches!(self, Argument::RemoteMutRef(..));
+            *self = Argument::Decoded(Box::new(borrow), is_mutable);
+        }
         match self {
             Argument::Serialised(s) => {
-                let arg: T = serde_json::from_str(&s).unwrap();
-                return WrapArg::Owned(arg);
+                let arg: T = decode(s).map_err(|e| mismatch::<T>(&e.0))?;
+                Ok(WrapArg::Owned(arg))
+            },
+            Argument::Moved(s) => {
+                let arg: T = decode(s).map_err(|e| mismatch::<T>(&e.0))?;
+                Ok(WrapArg::Owned(arg.own_remote()))
             },
             Argument::Owned(b) => {
-                let arg = b.downcast_ref::<T>().unwrap().to_owned();
-                return WrapArg::Owned(arg);
+                let arg = b.downcast_ref::<T>().ok_or_else(|| mismatch::<T>("wrong resource type"))?.to_owned();
+                Ok(WrapArg::Owned(arg))
             },
             Argument::Ref(b, is_ref) => {
                 if *is_ref {
-                    let result = &b.downcast_ref::<ConstPtr<T>>();
-                    match result {
+                    match b.downcast_ref::<ConstPtr<T>>() {
                         Some(dptr) => {
                             unsafe {
                                 let back: &T = dptr.0.as_ref().unwrap();
-                                return WrapArg::Ref(back);
+                                Ok(WrapArg::Ref(back))
                             }
                         },
                         None => { // mut ref -> ref
-                            let dptr: &*mut T = &b.downcast_ref::<MutPtr<T>>().unwrap().0;
+                            let dptr: &*mut T = &b.downcast_ref::<MutPtr<T>>().ok_or_else(|| mismatch::<T>("wrong reference type"))?.0;
                             unsafe {
                                 let back: &T = dptr.as_ref().unwrap();
-                                return WrapArg::Ref(back);
+                                Ok(WrapArg::Ref(back))
                             }
                         }
                     }
                 } else {
-                    let arg = b.downcast_ref::<T>().unwrap();
-                    return WrapArg::Ref(arg);
+                    let arg = b.downcast_ref::<T>().ok_or_else(|| mismatch::<T>("wrong resource type"))?;
+                    Ok(WrapArg::Ref(arg))
                 }
             },
             Argument::MutRef(ref mut b, is_ref) => {
                 if *is_ref {
-                    let dptr: &*mut T = &b.downcast_mut::<MutPtr<T>>().unwrap().0;
+                    let dptr: &*mut T = &b.downcast_mut::<MutPtr<T>>().ok_or_else(|| mismatch::<T>("wrong mutable reference type"))?.0;
                     unsafe {
                         let back: &mut T = dptr.as_mut().unwrap();
-                        return WrapArg::MutRef(back);
+                        Ok(WrapArg::MutRef(back))
                     }
                 } else {
-                    let arg = b.downcast_mut::<T>().unwrap();
-                    return WrapArg::MutRef(arg);
+                    let arg = b.downcast_mut::<T>().ok_or_else(|| mismatch::<T>("wrong resource type"))?;
+                    Ok(WrapArg::MutRef(arg))
                 }
             },
-            Argument::RemoteRef(s) => {
-                let deserialised: T = serde_json::from_str(&s).unwrap();
-                let borrow: T = deserialised.borrow_remote();
-                let remote: Box<dyn Any> = Box::new(borrow);
-                unsafe {
-                    REFS.push(remote); // hold the value in global varible for a longer lifetime
-                    WrapArg::Ref(REFS.last().unwrap().downcast_ref::<T>().unwrap())
-                }
+            Argument::Decoded(b, false) => {
+                let arg = b.downcast_ref::<T>().ok_or_else(|| mismatch::<T>("wrong proxy type"))?;
+                Ok(WrapArg::Ref(arg))
             },
-            Argument::RemoteMutRef(s) => {
-                let deserialised: T = serde_json::from_str(&s).unwrap();
-                let borrow: T = deserialised.borrow_remote();
-                let remote: Box<dyn Any> = Box::new(borrow);
-                unsafe {
-                    REFS.push(remote); // hold the value in global varible for a longer lifetime
-                    WrapArg::MutRef(REFS.last_mut().unwrap().downcast_mut::<T>().unwrap())
-                }
-            }
+            Argument::Decoded(b, true) => {
+                let arg = b.downcast_mut::<T>().ok_or_else(|| mismatch::<T>("wrong proxy type"))?;
+                Ok(WrapArg::MutRef(arg))
+            },
+            Argument::RemoteRef(_) | Argument::RemoteMutRef(_) => unreachable!() // decoded above
         }
     }
 }
 
+/* Checks that a call has as many arguments as the function takes, before any of them is decoded */
+pub fn expect_args(args: &[Argument], count: usize) -> Result<(), CallError> {
+    if args.len() == count {
+        Ok(())
+    } else {
+        Err((ErrorCode::ArgumentMismatch, format!("expected {} arguments, got {}", count, args.len())))
+    }
+}
+
+/* Takes the last argument of a call, the client may have sent fewer than the function expects */
+pub fn pop_arg<'a>(args: &mut Vec<Argument<'a>>) -> Result<Argument<'a>, CallError> {
+    args.pop().ok_or((ErrorCode::ArgumentMismatch, "missing argument".to_string()))
+}
+
+fn mismatch<T>(detail: &str) -> CallError {
+    (ErrorCode::ArgumentMismatch, format!("expected {}: {}", type_name::<T>(), detail))
+}
+
 impl <'a, T> Extract<T> for WrapArg<'a, T> {
-    fn extract(self) -> T {
+    fn extract(self) -> Result<T, CallError> {
         match self {
             WrapArg::Owned(b) => {
-                b
+                Ok(b)
             },
-            _ => panic!("wrong cases for owned")
+            _ => Err(mismatch::<T>("an owned value is required"))
         }
     }
 }
 
 impl <'a, T> Extract<&'a T> for WrapArg<'a, T> {
-    fn extract(self) -> &'a T {
+    fn extract(self) -> Result<&'a T, CallError> {
         match self {
             WrapArg::Ref(b) => {
-                b
+                Ok(b)
             },
-            _ => panic!("wrong cases for ref")
+            _ => Err(mismatch::<T>("a reference is required"))
         }
     }
 }
 
 impl <'a, T> Extract<&'a mut T> for WrapArg<'a, T> {
-    fn extract(self) -> &'a mut T {
+    fn extract(self) -> Result<&'a mut T, CallError> {
         match self {
             WrapArg::MutRef(b) => {
-                b
+                Ok(b)
             },
-            _ => panic!("wrong cases for mut ref")
+            _ => Err(mismatch::<T>("a mutable reference is required"))
         }
     }
 }
 
+/* The serialised result with its is_local tag and the boxed result, or why the call failed */
+pub type CallResult = Result<((Vec<u8>, bool), Box<dyn Any + Send + Sync>), CallError>;
+
 pub trait GenCall: Send + Sync + GenCallClone {
-    fn call(&self, a: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>);
+    fn call(&self, a: Vec<Argument>) -> CallResult;
 }
 
 pub trait GenCallClone {
@@ -166,7 +220,7 @@ impl Clone for Box<dyn GenCall> {
 }
 
 pub trait Extract<T> {
-    fn extract(self) -> T;
+    fn extract(self) -> Result<T, CallError>;
 }
 
 // pub struct Wrap<'a, T>(pub &'a mut Option<T>);
@@ -190,258 +244,83 @@ pub trait Extract<T> {
 pub enum ResultOp {
     Ref,
     MutRef,
-    Owned
+    Owned,
+    TryInit // a constructor returning Result<T, E>, T is stored and an error fails the call
 }
 
-#[macro_export]
-macro_rules! register {
-    // one arg - return ref
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::Ref), $args_ty_plain:ty, $args_ty:ty) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn($args_ty) -> $res_ty,
-        }
-
-        impl GenCall for $name {
-            fn call(&self, mut args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>) {
-                let mut arg = args.pop().unwrap();
-                let w = arg.get_arg::<$args_ty_plain>();
-                let result: $res_ty = (self.ptr)(w.extract());
-                let serialised = result.tagged_string();
-                let ptr: *const $res_ty_plain = result;
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(ConstPtr(ptr));
-                return (serialised, boxed);
-            }
-        }
-        $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
-    };
-    // one arg - return mut ref
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::MutRef), $args_ty_plain:ty, $args_ty:ty) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn($args_ty) -> $res_ty,
-        }
+#[doc(hidden)]
+pub use umi_macros_proc::gen_call;
 
-        impl GenCall for $name {
-            fn call(&self, mut args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>) {
-                let mut arg = args.pop().unwrap();
-                let w = arg.get_arg::<$args_ty_plain>();
-                let result = (self.ptr)(w.extract());
-                let serialised = result.tagged_string();
-                let ptr: *mut $res_ty_plain = result;
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(MutPtr(ptr));
-                return (serialised, boxed);
-            }
-        }
-        $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
-    };
-    // one arg - return owned
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty:ty, ResultOp::Owned), $args_ty_plain:ty, $args_ty:ty) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn($args_ty) -> $res_ty,
-        }
+// brought in by setup_registry!() with the rest of the module, for the registrations generated by umi_register
+#[doc(hidden)]
+pub use crate::register;
 
-        impl GenCall for $name {
-            fn call(&self, mut args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>) {
-                let mut arg = args.pop().unwrap();
-                let w = arg.get_arg::<$args_ty_plain>();
-                let result = (self.ptr)(w.extract());
-                let serialised = result.tagged_string();
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(result);
-                return (serialised, boxed);
-            }
-        }
+/* Registers a function with any number of arguments, each given by its type followed, after all the types, by its ownership */
+#[macro_export]
+macro_rules! register {
+    // return ref
+    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::Ref) $(, $args_ty:ty)*) => {
+        $crate::registry::gen_call!($crate, Ref, $name, $res_ty_plain, $res_ty $(, $args_ty)*);
         $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
     };
-    // two args - return ref
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::Ref), $args_ty_plain1:ty, $args_ty_plain2:ty, $args_ty1:ty, $args_ty2:ty) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn($args_ty1, $args_ty2) -> $res_ty,
-        }
-
-        impl GenCall for $name {
-            fn call(&self, mut args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>){
-                let mut arg2 = args.pop().unwrap();
-                let mut arg1 = args.pop().unwrap();
-                let w1 = arg1.get_arg::<$args_ty_plain1>();
-                let w2 = arg2.get_arg::<$args_ty_plain2>();
-                let result = (self.ptr)(w1.extract(), w2.extract());
-                let serialised = result.tagged_string();
-                let ptr: *const $res_ty_plain = result;
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(ConstPtr(ptr));
-                return (serialised, boxed);
-            }
-        }
+    // return mut ref
+    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::MutRef) $(, $args_ty:ty)*) => {
+        $crate::registry::gen_call!($crate, MutRef, $name, $res_ty_plain, $res_ty $(, $args_ty)*);
         $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
     };
-    // two args - return mut ref
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::MutRef), $args_ty_plain1:ty, $args_ty_plain2:ty, $args_ty1:ty, $args_ty2:ty) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn($args_ty1, $args_ty2) -> $res_ty,
-        }
-
-        impl GenCall for $name {
-            fn call(&self, mut args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>){
-                let mut arg2 = args.pop().unwrap();
-                let mut arg1 = args.pop().unwrap();
-                let w1 = arg1.get_arg::<$args_ty_plain1>();
-                let w2 = arg2.get_arg::<$args_ty_plain2>();
-                let result = (self.ptr)(w1.extract(), w2.extract());
-                let serialised = result.tagged_string();
-                let ptr: *mut $res_ty_plain = result;
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(MutPtr(ptr));
-                return (serialised, boxed);
-            }
-        }
+    // return owned
+    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty:ty, ResultOp::Owned) $(, $args_ty:ty)*) => {
+        $crate::registry::gen_call!($crate, Owned, $name, $res_ty, $res_ty $(, $args_ty)*);
         $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
     };
-    // two args - retrurn owned
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty:ty, ResultOp::Owned), $args_ty_plain1:ty, $args_ty_plain2:ty, $args_ty1:ty, $args_ty2:ty) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn($args_ty1, $args_ty2) -> $res_ty,
-        }
-
-        impl GenCall for $name {
-            fn call(&self, mut args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>){
-                let mut arg2 = args.pop().unwrap();
-                let mut arg1 = args.pop().unwrap();
-                let w1 = arg1.get_arg::<$args_ty_plain1>();
-                let w2 = arg2.get_arg::<$args_ty_plain2>();
-                let result = (self.ptr)(w1.extract(), w2.extract());
-                let serialised = result.tagged_string();
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(result);
-                return (serialised, boxed);
-            }
-        }
+    // return the value of a fallible constructor
+    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::TryInit) $(, $args_ty:ty)*) => {
+        $crate::registry::gen_call!($crate, TryInit, $name, $res_ty_plain, $res_ty $(, $args_ty)*);
         $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
-    };
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::encode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-    // three args - return ref
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::Ref), $args_ty_plain1:ty, $args_ty_plain2:ty, $args_ty_plain3:ty, $args_ty1:ty, $args_ty2:ty, $args_ty3:ty) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn($args_ty1, $args_ty2, $args_ty3) -> $res_ty,
-        }
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
 
-        impl GenCall for $name {
-            fn call(&self, mut args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>){
-                let mut arg3 = args.pop().unwrap();
-                let mut arg2 = args.pop().unwrap();
-                let mut arg1 = args.pop().unwrap();
-                let w1 = arg1.get_arg::<$args_ty_plain1>();
-                let w2 = arg2.get_arg::<$args_ty_plain2>();
-                let w3 = arg3.get_arg::<$args_ty_plain3>();
-                let result = (self.ptr)(w1.extract(), w2.extract(), w3.extract());
-                let serialised = result.tagged_string();
-                let ptr: *const $res_ty_plain = result;
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(ConstPtr(ptr));
-                return (serialised, boxed);
-            }
-        }
-        $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
-    };
-    // three args - return mut ref
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::MutRef), $args_ty_plain1:ty, $args_ty_plain2:ty, $args_ty_plain3:ty, $args_ty1:ty, $args_ty2:ty, $args_ty3:ty) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn($args_ty1, $args_ty2, $args_ty3) -> $res_ty,
-        }
+    // stands for a proxy, counting how many copies are freed
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Probe(u32);
 
-        impl GenCall for $name {
-            fn call(&self, mut args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>){
-                let mut arg3 = args.pop().unwrap();
-                let mut arg2 = args.pop().unwrap();
-                let mut arg1 = args.pop().unwrap();
-                let w1 = arg1.get_arg::<$args_ty_plain1>();
-                let w2 = arg2.get_arg::<$args_ty_plain2>();
-                let w3 = arg3.get_arg::<$args_ty_plain3>();
-                let result = (self.ptr)(w1.extract(), w2.extract(), w3.extract());
-                let serialised = result.tagged_string();
-                let ptr: *mut $res_ty_plain = result;
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(MutPtr(ptr));
-                return (serialised, boxed);
-            }
-        }
-        $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
-    };
-    // three args - retrurn owned
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty:ty, ResultOp::Owned), $args_ty_plain1:ty, $args_ty_plain2:ty, $args_ty_plain3:ty, $args_ty1:ty, $args_ty2:ty, $args_ty3:ty) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn($args_ty1, $args_ty2, $args_ty3) -> $res_ty,
-        }
-
-        impl GenCall for $name {
-            fn call(&self, mut args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>){
-                let mut arg3 = args.pop().unwrap();
-                let mut arg2 = args.pop().unwrap();
-                let mut arg1 = args.pop().unwrap();
-                let w1 = arg1.get_arg::<$args_ty_plain1>();
-                let w2 = arg2.get_arg::<$args_ty_plain2>();
-                let w3 = arg3.get_arg::<$args_ty_plain3>();
-                let result = (self.ptr)(w1.extract(), w2.extract(), w3.extract());
-                let serialised = result.tagged_string();
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(result);
-                return (serialised, boxed);
-            }
-        }
-        $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
-    };
-    // zero arg - return ref
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::Ref)) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn() -> $res_ty,
+    impl BorrowRemote for Probe {
+        fn borrow_remote(&self) -> Self {
+            Probe(self.0)
         }
+    }
 
-        impl GenCall for $name {
-            fn call(&self, args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>) {
-                let result = (self.ptr)();
-                let serialised = result.tagged_string();
-                let ptr: *const $res_ty_plain = result;
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(ConstPtr(ptr));
-                return (serialised, boxed);
-            }
-        }
-        $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
-    };
-    // zero arg - return mut ref
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty_plain:ty, $res_ty:ty, ResultOp::MutRef)) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn() -> $res_ty,
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
         }
+    }
 
-        impl GenCall for $name {
-            fn call(&self, args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>) {
-                let result = (self.ptr)();
-                let serialised = result.tagged_string();
-                let ptr: *mut $res_ty_plain = result;
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(MutPtr(ptr));
-                return (serialised, boxed);
-            }
-        }
-        $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
-    };
-    // zero arg - return non-ref
-    ($map:ident, $name:ident, $fn_name:path, $fn_ty:ty, ($res_ty:ty, ResultOp::Owned)) => {
-        #[derive(Clone)]
-        pub struct $name {
-            ptr: fn() -> $res_ty,
-        }
+    // a method taking &mut Probe, its argument lives until the call returns
+    fn bump(mut args: Vec<Argument>) -> u32 {
+        let mut arg = pop_arg(&mut args).unwrap();
+        let probe: &mut Probe = arg.get_arg::<Probe>().unwrap().extract().unwrap();
+        probe.0 += 1;
+        probe.0
+    }
 
-        impl GenCall for $name {
-            fn call(&self, args: Vec<Argument>) -> ((String, bool), Box<dyn Any + Send + Sync>) {
-                let result = (self.ptr)();
-                let serialised = result.tagged_string();
-                let boxed: Box<dyn Any + Send + Sync> = Box::new(result);
-                return (serialised, boxed);
-            }
-        }
-        $map.insert(fn_type_name(&$fn_name), Box::new($name {ptr: ($fn_name as $fn_ty)}))
+    #[test]
+    fn remote_borrows_are_freed_with_their_argument() {
+        let args = vec![Argument::RemoteMutRef(encode(&Probe(7)).unwrap())];
+        let before = DROPPED.load(Ordering::SeqCst);
+        assert_eq!(bump(args), 8);
+        // both the decoded proxy and its borrow are freed, nothing is kept after the call
+        assert_eq!(DROPPED.load(Ordering::SeqCst), before + 2);
+
+        let mut arg = Argument::RemoteRef(encode(&Probe(7)).unwrap());
+        let borrowed: Result<&mut Probe, CallError> = arg.get_arg::<Probe>().unwrap().extract();
+        assert!(borrowed.is_err());
     }
-}
\ No newline at end of file
+}
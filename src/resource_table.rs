@@ -0,0 +1,907 @@
+use std::any::Any;
+use std::cell::UnsafeCell;
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::sync::{Arc, Mutex, Condvar, RwLock, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::message_serialisation::{ErrorCode, CallError};
+use crate::endpoint::{IDGen};
+
+use umi_macros::{ID};
+
+// bool - is an entry for resouce or reference
+type Slot = Option<(Box<dyn Any + Send + Sync>, bool)>;
+
+/* How a request uses a resource, following the variables of the request */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Shared, // Variable::RefRemote
+    Exclusive, // Variable::MutRefRemote
+    Take, // Variable::OwnedRemote, the resource is moved out of the table
+}
+
+#[derive(Default)]
+struct LockState {
+    readers: Vec<ThreadId>, // a request may borrow a resource again when it calls back into its endpoint
+    writer: Option<ThreadId>,
+}
+
+impl LockState {
+    fn holders(&self) -> impl Iterator<Item = &ThreadId> {
+        self.writer.iter().chain(self.readers.iter())
+    }
+
+    fn is_free(&self) -> bool {
+        self.writer.is_none() && self.readers.is_empty()
+    }
+}
+
+/* Who holds and who waits for the resources of a table.
+ * A request is identified by the thread serving it, requests calling back into their endpoint run on the same thread.
+ * A request reaching the table through another endpoint runs on another thread, so cycles through other endpoints are not seen
+ */
+#[derive(Default)]
+struct LockGraph {
+    held: HashMap<ID, LockState>,
+    waiting: HashMap<ThreadId, ID>,
+}
+
+impl LockGraph {
+    // whether id is held by me, or by a request waiting, directly or not, for a resource held by me
+    fn leads_to(&self, id: ID, me: ThreadId) -> bool {
+        let mut visited = HashSet::new();
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            if let Some(state) = self.held.get(&id) {
+                for holder in state.holders() {
+                    if *holder == me {
+                        return true;
+                    }
+                    if let Some(next) = self.waiting.get(holder) {
+                        pending.push(*next);
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+/* Reader/writer locks held for the whole call, not for a scope */
+#[derive(Default)]
+struct Locks {
+    graph: Mutex<LockGraph>,
+    released: Condvar,
+}
+
+impl Locks {
+    fn graph(&self) -> MutexGuard<'_, LockGraph> {
+        self.graph.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /* Waits for the lock of a resource, unless waiting would never end */
+    fn acquire(&self, id: ID, access: Access) -> Result<(), CallError> {
+        let me = thread::current().id();
+        let mut graph = self.graph();
+        loop {
+            let state = graph.held.entry(id).or_default();
+            let granted = match access {
+                Access::Shared if state.writer.is_none() => {
+                    state.readers.push(me);
+                    true
+                },
+                Access::Exclusive | Access::Take if state.is_free() => {
+                    state.writer = Some(me);
+                    true
+                },
+                _ => false
+            };
+            if granted {
+                return Ok(());
+            }
+            if graph.leads_to(id, me) {
+                return Err((ErrorCode::Deadlock, format!("{:?} is held by this request or by a request waiting for it", id)));
+            }
+            graph.waiting.insert(me, id);
+            graph = self.released.wait(graph).unwrap_or_else(PoisonError::into_inner);
+            graph.waiting.remove(&me);
+        }
+    }
+
+    fn release(&self, id: ID, access: Access, holder: ThreadId) {
+        let mut graph = self.graph();
+        if let Some(state) = graph.held.get_mut(&id) {
+            match access {
+                Access::Shared => {
+                    if let Some(i) = state.readers.iter().position(|r| *r == holder) {
+                        state.readers.remove(i);
+                    }
+                },
+                Access::Exclusive | Access::Take => state.writer = None,
+            }
+            if state.is_free() {
+                graph.held.remove(&id);
+            }
+        }
+        self.released.notify_all();
+    }
+}
+
+/* A resource, only reached through the Borrows of the request locking it */
+pub struct Resource {
+    value: UnsafeCell<Slot>,
+    poisoned: AtomicBool,
+    sources: Option<Vec<ID>>, // for a borrow entry, the resources it points into
+}
+
+// the value is only reached through Borrows, which hold the lock
+unsafe impl Sync for Resource {}
+
+impl Resource {
+    fn new(value: Box<dyn Any + Send + Sync>, is_ref: bool, sources: Option<Vec<ID>>) -> Resource {
+        Resource {
+            value: UnsafeCell::new(Some((value, is_ref))),
+            poisoned: AtomicBool::new(false),
+            sources,
+        }
+    }
+
+    pub fn is_borrow(&self) -> bool {
+        self.sources.is_some()
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+}
+
+/* The resources of an endpoint. Requests on different resources run in parallel */
+#[derive(Default)]
+pub struct ResourceTable {
+    entries: RwLock<HashMap<ID, Arc<Resource>>>,
+    locks: Arc<Locks>,
+    leases: Mutex<HashMap<ID, Instant>>, // expiry of the resources owned by proxies
+    derived: Mutex<HashMap<ID, Vec<ID>>>, // the borrow entries pointing into a resource
+    names: Mutex<HashMap<String, ID>>, // the resources bound to a name, owned by their name
+    ids: IDGen,
+}
+
+impl ResourceTable {
+    pub fn new() -> ResourceTable {
+        ResourceTable::default()
+    }
+
+    /* A fresh ID for a resource of this table */
+    pub fn next_id(&self) -> ID {
+        self.ids.next_id()
+    }
+
+    pub fn insert(&self, id: ID, value: Box<dyn Any + Send + Sync>, is_ref: bool) {
+        self.entries.write().unwrap_or_else(PoisonError::into_inner).insert(id, Arc::new(Resource::new(value, is_ref, None)));
+    }
+
+    /* Inserts a reference returned by a method, pointing into the resources in sources.
+     * Requests using the entry lock its sources as well. The entry is removed with any of them,
+     * and when a request not using the entry borrows one of them mutably
+     */
+    pub fn insert_borrow(&self, id: ID, value: Box<dyn Any + Send + Sync>, sources: &[ID]) {
+        let mut derived = self.derived.lock().unwrap_or_else(PoisonError::into_inner);
+        for source in sources {
+            derived.entry(*source).or_default().push(id);
+        }
+        self.entries.write().unwrap_or_else(PoisonError::into_inner).insert(id, Arc::new(Resource::new(value, true, Some(sources.to_vec()))));
+    }
+
+    /* Removes a resource and the borrow entries derived from it.
+     * A request still using the resource keeps it alive until it finishes
+     */
+    pub fn remove(&self, id: &ID) -> bool {
+        self.leases.lock().unwrap_or_else(PoisonError::into_inner).remove(id);
+        self.names.lock().unwrap_or_else(PoisonError::into_inner).retain(|_, bound| bound != id);
+        let removed = self.entries.write().unwrap_or_else(PoisonError::into_inner).remove(id);
+        let derived = self.derived.lock().unwrap_or_else(PoisonError::into_inner).remove(id).unwrap_or_default();
+        for borrow in &derived {
+            self.remove(borrow);
+        }
+        match removed {
+            Some(resource) => {
+                if let Some(sources) = &resource.sources {
+                    let mut derived = self.derived.lock().unwrap_or_else(PoisonError::into_inner);
+                    for source in sources {
+                        if let Some(borrows) = derived.get_mut(source) {
+                            borrows.retain(|b| b != id);
+                        }
+                    }
+                }
+                true
+            },
+            None => false
+        }
+    }
+
+    // removes a borrow entry, resources are only removed by remove()
+    pub fn release(&self, id: &ID) -> bool {
+        match self.get(id) {
+            Some(resource) if resource.is_borrow() => self.remove(id),
+            _ => false
+        }
+    }
+
+    /* The resource is removed by reap_leases unless the lease is renewed within lease */
+    pub fn lease(&self, id: ID, lease: Duration) {
+        self.leases.lock().unwrap_or_else(PoisonError::into_inner).insert(id, Instant::now() + lease);
+    }
+
+    // returns the ids that are not in the table
+    pub fn renew_leases(&self, ids: &[ID], lease: Duration) -> Vec<ID> {
+        let until = Instant::now() + lease;
+        let mut leases = self.leases.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut unknown = Vec::new();
+        for id in ids {
+            if !self.contains(id) {
+                unknown.push(*id);
+            } else if let Some(expiry) = leases.get_mut(id) {
+                *expiry = until;
+            }
+        }
+        unknown
+    }
+
+    /* Removes the resources whose lease expired before now, and returns their ids */
+    pub fn reap_leases(&self, now: Instant) -> Vec<ID> {
+        let expired: Vec<ID> = self.leases.lock().unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|(_, expiry)| **expiry <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            self.remove(id);
+        }
+        expired
+    }
+
+    /* Inserts a resource bound to name, e.g. from the main of a server, returns None when the name is taken */
+    pub fn insert_named(&self, name: &str, value: Box<dyn Any + Send + Sync>) -> Option<ID> {
+        let mut names = self.names.lock().unwrap_or_else(PoisonError::into_inner);
+        if names.contains_key(name) {
+            return None;
+        }
+        let id = self.next_id();
+        self.insert(id, value, false);
+        names.insert(name.to_string(), id);
+        Some(id)
+    }
+
+    /* Binds a resource to name, so that clients can attach to it.
+     * A bound resource belongs to its name: it has no lease and proxies do not drop it, it is removed by unbind().
+     * Returns false when the name is taken or the resource is not in the table
+     */
+    pub fn bind(&self, name: &str, id: ID) -> bool {
+        let mut names = self.names.lock().unwrap_or_else(PoisonError::into_inner);
+        match self.get(&id) {
+            Some(resource) if !resource.is_borrow() && !names.contains_key(name) => {
+                names.insert(name.to_string(), id);
+                self.leases.lock().unwrap_or_else(PoisonError::into_inner).remove(&id);
+                true
+            },
+            _ => false
+        }
+    }
+
+    // removes the name and the resource bound to it
+    pub fn unbind(&self, name: &str) -> Option<ID> {
+        let id = self.names.lock().unwrap_or_else(PoisonError::into_inner).remove(name)?;
+        self.remove(&id);
+        Some(id)
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<ID> {
+        self.names.lock().unwrap_or_else(PoisonError::into_inner).get(name).copied()
+    }
+
+    pub fn is_bound(&self, id: &ID) -> bool {
+        self.names.lock().unwrap_or_else(PoisonError::into_inner).values().any(|bound| bound == id)
+    }
+
+    // sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.names.lock().unwrap_or_else(PoisonError::into_inner).keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn contains(&self, id: &ID) -> bool {
+        self.entries.read().unwrap_or_else(PoisonError::into_inner).contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, id: &ID) -> Option<Arc<Resource>> {
+        self.entries.read().unwrap_or_else(PoisonError::into_inner).get(id).cloned()
+    }
+
+    /* Locks every resource of a request.
+     * Locks are taken in ID order so that requests locking the same resources cannot deadlock each other.
+     * A request borrowing a resource mutably and in any other way at the same time, directly or through borrow entries, is refused.
+     * A request calling back into its endpoint may borrow immutably again what its callers borrowed immutably,
+     * any other wait on its callers, or on requests waiting for them, is refused with ErrorCode::Deadlock.
+     */
+    pub fn borrow_all(&self, requests: &[(ID, Access)]) -> Result<Borrows, CallError> {
+        // a borrow entry points into its sources, which are locked with it
+        let mut expanded = requests.to_vec();
+        let mut i = 0;
+        while i < expanded.len() {
+            let (id, access) = expanded[i];
+            if let Some(sources) = self.get(&id).and_then(|r| r.sources.clone()) {
+                let access = if access == Access::Shared { Access::Shared } else { Access::Exclusive };
+                expanded.extend(sources.into_iter().map(|source| (source, access)));
+            }
+            i += 1;
+        }
+        let mut wanted: BTreeMap<ID, Access> = BTreeMap::new();
+        for (id, access) in &expanded {
+            match wanted.get(id) {
+                None => { wanted.insert(*id, *access); },
+                Some(Access::Shared) if *access == Access::Shared => {},
+                Some(_) => return Err((ErrorCode::ResourceBusy, format!("{:?} is borrowed mutably and in another way by the same request", id)))
+            }
+        }
+        let mut borrows = Borrows { held: Vec::new(), locks: Arc::clone(&self.locks), holder: thread::current().id() };
+        for (id, access) in wanted {
+            let resource = self.get(&id).ok_or_else(|| (ErrorCode::UnknownResource, format!("{:?}", id)))?;
+            self.locks.acquire(id, access)?;
+            borrows.held.push((id, resource, access));
+            let (_, resource, _) = borrows.held.last().unwrap();
+            // another request may have moved the resource out while this one waited for it
+            if unsafe { (*resource.value.get()).is_none() } {
+                return Err((ErrorCode::UnknownResource, format!("{:?}", id)));
+            }
+            if resource.is_poisoned() {
+                return Err((ErrorCode::ResourcePoisoned, format!("{:?}", id)));
+            }
+        }
+        // a borrow entry may have been invalidated while this request waited for its sources
+        for (id, resource, _) in &borrows.held {
+            if resource.is_borrow() && !self.get(id).is_some_and(|current| Arc::ptr_eq(&current, resource)) {
+                return Err((ErrorCode::UnknownResource, format!("{:?}", id)));
+            }
+        }
+        // a resource borrowed mutably may move what the borrow entries into it point to,
+        // only the entries this request uses stay valid
+        for (id, _, access) in &borrows.held {
+            if *access == Access::Exclusive {
+                let derived = self.derived.lock().unwrap_or_else(PoisonError::into_inner).get(id).cloned().unwrap_or_default();
+                for borrow in derived.iter().filter(|borrow| borrows.find(borrow).is_none()) {
+                    self.remove(borrow);
+                }
+            }
+        }
+        Ok(borrows)
+    }
+
+    /* Removes the resources a request moved into its call, once the call succeeded.
+     * Their values are dropped before the locks are released, so that the requests waiting for them find them gone
+     */
+    pub fn remove_taken(&self, borrows: &Borrows) {
+        for (id, resource, access) in &borrows.held {
+            if *access == Access::Take {
+                unsafe { (*resource.value.get()).take(); }
+                self.remove(id);
+            }
+        }
+    }
+}
+
+/* The resources locked for one request, released when dropped */
+pub struct Borrows {
+    held: Vec<(ID, Arc<Resource>, Access)>,
+    locks: Arc<Locks>,
+    holder: ThreadId,
+}
+
+impl Borrows {
+    fn find(&self, id: &ID) -> Option<&(ID, Arc<Resource>, Access)> {
+        self.held.iter().find(|(held, _, _)| held == id)
+    }
+
+    pub fn get(&self, id: &ID) -> Option<&(Box<dyn Any + Send + Sync>, bool)> {
+        match self.find(id)? {
+            (_, resource, Access::Shared) => unsafe { (*resource.value.get()).as_ref() },
+            _ => None
+        }
+    }
+
+    /* The request holds the only lock on an exclusive resource, and borrow_all refused duplicates of it */
+    #[allow(clippy::mut_from_ref)]
+    pub fn get_mut(&self, id: &ID) -> Option<&mut (Box<dyn Any + Send + Sync>, bool)> {
+        match self.find(id)? {
+            (_, resource, Access::Exclusive) => unsafe { (*resource.value.get()).as_mut() },
+            _ => None
+        }
+    }
+
+    // a resource moved into the call stays in the table until the call succeeded, see ResourceTable::remove_taken
+    pub fn get_taken(&self, id: &ID) -> Option<&(Box<dyn Any + Send + Sync>, bool)> {
+        match self.find(id)? {
+            (_, resource, Access::Take) => unsafe { (*resource.value.get()).as_ref() },
+            _ => None
+        }
+    }
+
+    // the resources borrowed mutably may be half-updated
+    pub fn poison_exclusive(&self) {
+        for (_, resource, access) in &self.held {
+            if *access == Access::Exclusive {
+                resource.poisoned.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Drop for Borrows {
+    fn drop(&mut self) {
+        for (id, _, access) in &self.held {
+            self.locks.release(*id, *access, self.holder);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{SystemTime};
+    use umi_macros::{Token};
+    use std::sync::mpsc;
+    use std::thread;
+    use crate::endpoint::{UMIEndpoint, PanicPolicy, random_token};
+    use serde::{Serialize, Deserialize};
+    use crate::message_serialisation::{Message, ReturnVar, InvokeOp, ErrorCode, invoke};
+    use crate::codec::encode;
+    use umi_macros::{Variable, ID};
+    use crate::error::UmiError;
+    use crate::registry::*;
+    use crate::proxy_lib::*;
+    use crate::utils::{fn_type_name};
+    use crate::codec::decode;
+    use crate::lease::renew;
+    use umi_macros::Node;
+    use crate::register;
+    use std::any::Any;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn table_with(ids: &[ID]) -> Arc<ResourceTable> {
+        let table = Arc::new(ResourceTable::new());
+        for id in ids {
+            table.insert(*id, Box::new(0u32), false);
+        }
+        table
+    }
+
+    #[test]
+    fn conflicting_borrows_in_one_request_are_refused() {
+        let a = (SystemTime::now(), 0, Token([0; 16]));
+        let table = table_with(&[a]);
+        assert!(matches!(table.borrow_all(&[(a, Access::Exclusive), (a, Access::Shared)]), Err((ErrorCode::ResourceBusy, _))));
+        assert!(matches!(table.borrow_all(&[(a, Access::Take), (a, Access::Exclusive)]), Err((ErrorCode::ResourceBusy, _))));
+        let borrows = table.borrow_all(&[(a, Access::Shared), (a, Access::Shared)]).unwrap();
+        assert!(borrows.get(&a).is_some());
+    }
+
+    #[test]
+    fn exclusive_borrow_blocks_only_its_resource() {
+        let (a, b) = ((SystemTime::now(), 0, Token([0; 16])), (SystemTime::now(), 1, Token([0; 16])));
+        let table = table_with(&[a, b]);
+        let borrows = table.borrow_all(&[(a, Access::Exclusive)]).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let other = Arc::clone(&table);
+        thread::spawn(move || {
+            drop(other.borrow_all(&[(b, Access::Exclusive)]).unwrap());
+            sender.send("b").unwrap();
+            drop(other.borrow_all(&[(a, Access::Shared)]).unwrap());
+            sender.send("a").unwrap();
+        });
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok("b"));
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(borrows);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok("a"));
+    }
+
+    #[test]
+    fn taken_resource_leaves_the_table_once_removed() {
+        let a = (SystemTime::now(), 0, Token([0; 16]));
+        let table = table_with(&[a]);
+        // a failed call leaves the resource in place
+        drop(table.borrow_all(&[(a, Access::Take)]).unwrap());
+        assert!(table.contains(&a));
+        let borrows = table.borrow_all(&[(a, Access::Take)]).unwrap();
+        assert!(borrows.get_taken(&a).is_some());
+        table.remove_taken(&borrows);
+        assert!(!table.contains(&a));
+        assert!(borrows.get_taken(&a).is_none());
+    }
+
+    #[test]
+    fn nested_borrows_conflicting_with_callers_are_deadlocks() {
+        let (a, b) = ((SystemTime::now(), 0, Token([0; 16])), (SystemTime::now(), 1, Token([0; 16])));
+        let table = table_with(&[a, b]);
+        let outer = table.borrow_all(&[(a, Access::Shared), (b, Access::Exclusive)]).unwrap();
+        // the same thread stands for a request calling back into its endpoint
+        let nested = table.borrow_all(&[(a, Access::Shared)]).unwrap();
+        assert!(nested.get(&a).is_some());
+        drop(nested);
+        assert!(matches!(table.borrow_all(&[(a, Access::Exclusive)]), Err((ErrorCode::Deadlock, _))));
+        assert!(matches!(table.borrow_all(&[(b, Access::Shared)]), Err((ErrorCode::Deadlock, _))));
+        drop(outer);
+        assert!(table.borrow_all(&[(a, Access::Exclusive), (b, Access::Exclusive)]).is_ok());
+    }
+
+    #[test]
+    fn crossing_waits_are_deadlocks() {
+        let (a, b) = ((SystemTime::now(), 0, Token([0; 16])), (SystemTime::now(), 1, Token([0; 16])));
+        let table = table_with(&[a, b]);
+        let borrows = table.borrow_all(&[(a, Access::Exclusive)]).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let other = Arc::clone(&table);
+        thread::spawn(move || {
+            let held = other.borrow_all(&[(b, Access::Exclusive)]).unwrap();
+            sender.send(false).unwrap();
+            let waited = other.borrow_all(&[(a, Access::Exclusive)]).is_ok();
+            drop(held);
+            sender.send(waited).unwrap();
+        });
+        assert!(!receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+        while table.locks.graph().waiting.is_empty() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // this request holds a and the other one waits for it while holding b
+        assert!(matches!(table.borrow_all(&[(b, Access::Shared)]), Err((ErrorCode::Deadlock, _))));
+        drop(borrows);
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+
+    #[test]
+    fn expired_leases_are_reaped() {
+        let (a, b, c) = ((SystemTime::now(), 0, Token([0; 16])), (SystemTime::now(), 1, Token([0; 16])), (SystemTime::now(), 2, Token([0; 16])));
+        let unknown = (SystemTime::now(), 3, Token([0; 16]));
+        let table = table_with(&[a, b, c]);
+        let now = Instant::now();
+        table.lease(a, Duration::from_secs(1));
+        table.lease(b, Duration::from_secs(1));
+        assert_eq!(table.renew_leases(&[b, unknown], Duration::from_secs(3)), vec![unknown]);
+        assert!(table.reap_leases(now).is_empty());
+        // c is not leased and stays until it is dropped
+        assert_eq!(table.reap_leases(now + Duration::from_secs(2)), vec![a]);
+        assert_eq!(table.reap_leases(now + Duration::from_secs(4)), vec![b]);
+        assert!(table.contains(&c));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn names_own_their_resources() {
+        let table = ResourceTable::new();
+        let shared = table.insert_named("shared", Box::new(0u32)).unwrap();
+        assert!(table.insert_named("shared", Box::new(1u32)).is_none());
+        assert_eq!(table.len(), 1);
+
+        // a leased resource bound to a name is not reaped anymore
+        let created = table.next_id();
+        table.insert(created, Box::new(2u32), false);
+        table.lease(created, Duration::from_secs(1));
+        assert!(!table.bind("shared", created));
+        assert!(table.bind("created", created));
+        assert!(table.reap_leases(Instant::now() + Duration::from_secs(2)).is_empty());
+        assert_eq!(table.names(), vec!["created".to_string(), "shared".to_string()]);
+        assert_eq!(table.lookup("shared"), Some(shared));
+        assert!(table.is_bound(&created));
+
+        assert_eq!(table.unbind("shared"), Some(shared));
+        assert!(!table.contains(&shared));
+        assert_eq!(table.unbind("shared"), None);
+        // a resource removed otherwise, e.g. moved into a method, takes its name with it
+        assert!(table.remove(&created));
+        assert!(table.names().is_empty());
+    }
+
+    #[test]
+    fn borrow_entries_follow_their_sources() {
+        let (a, b) = ((SystemTime::now(), 0, Token([0; 16])), (SystemTime::now(), 1, Token([0; 16])));
+        let (borrow, nested) = ((SystemTime::now(), 2, Token([0; 16])), (SystemTime::now(), 3, Token([0; 16])));
+        let table = table_with(&[a, b]);
+        table.insert_borrow(borrow, Box::new(0u32), &[a]);
+        table.insert_borrow(nested, Box::new(0u32), &[borrow]);
+        // using a borrow entry borrows its sources the same way
+        assert!(matches!(table.borrow_all(&[(a, Access::Exclusive), (nested, Access::Shared)]), Err((ErrorCode::ResourceBusy, _))));
+        assert!(table.borrow_all(&[(a, Access::Shared), (nested, Access::Shared)]).is_ok());
+
+        assert!(!table.release(&a));
+        assert!(table.release(&nested));
+        assert!(table.contains(&borrow));
+        table.insert_borrow(nested, Box::new(0u32), &[borrow]);
+        assert!(table.remove(&a));
+        assert!(!table.contains(&borrow));
+        assert!(!table.contains(&nested));
+        assert_eq!(table.len(), 1);
+        assert!(table.derived.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn mutable_borrows_of_a_source_invalidate_other_entries() {
+        let a = (SystemTime::now(), 0, Token([0; 16]));
+        let (shared, exclusive) = ((SystemTime::now(), 1, Token([0; 16])), (SystemTime::now(), 2, Token([0; 16])));
+        let table = table_with(&[a]);
+        table.insert_borrow(shared, Box::new(0u32), &[a]);
+        table.insert_borrow(exclusive, Box::new(0u32), &[a]);
+        // reading the source keeps its entries
+        drop(table.borrow_all(&[(a, Access::Shared)]).unwrap());
+        assert_eq!(table.len(), 3);
+        // the entry used by the request stays, the other one may point to memory the request moves
+        drop(table.borrow_all(&[(exclusive, Access::Exclusive)]).unwrap());
+        assert!(table.contains(&exclusive));
+        assert!(!table.contains(&shared));
+        drop(table.borrow_all(&[(a, Access::Exclusive)]).unwrap());
+        assert_eq!(table.len(), 1);
+        assert!(matches!(table.borrow_all(&[(exclusive, Access::Shared)]), Err((ErrorCode::UnknownResource, _))));
+    }
+
+    #[test]
+    fn entries_invalidated_while_waiting_are_refused() {
+        // the entry is locked before its source
+        let now = SystemTime::now();
+        let (borrow, a) = ((now, 0, Token([0; 16])), (now, 1, Token([0; 16])));
+        let table = table_with(&[a]);
+        table.insert_borrow(borrow, Box::new(0u32), &[a]);
+        let reader = table.borrow_all(&[(a, Access::Shared)]).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let other = Arc::clone(&table);
+        thread::spawn(move || {
+            sender.send(matches!(other.borrow_all(&[(borrow, Access::Exclusive)]), Err((ErrorCode::UnknownResource, _)))).unwrap();
+        });
+        while table.locks.graph().waiting.is_empty() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // e.g. by a request borrowing the source mutably before the waiting one
+        table.remove(&borrow);
+        drop(reader);
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+
+    // a resource whose method panics on its second call
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Counter(u32);
+
+    impl BorrowRemote for Counter {
+        fn borrow_remote(&self) -> Self {
+            panic!("This should never be called");
+        }
+    }
+
+    impl SerializeTagL for Counter {
+        fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+            (encode(self).unwrap(), true)
+        }
+    }
+
+    fn counter_new() -> Counter {
+        Counter(0)
+    }
+
+    fn counter_bump(counter: &mut Counter) -> bool {
+        counter.0 += 1;
+        if counter.0 > 1 {
+            panic!("counter overflow");
+        }
+        true
+    }
+
+    fn counter_peek(counter: &Counter) -> &Counter {
+        counter
+    }
+
+    // a resource whose method moves the strings it lends
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Bag(Vec<String>);
+
+    impl BorrowRemote for Bag {
+        fn borrow_remote(&self) -> Self {
+            panic!("This should never be called");
+        }
+    }
+
+    impl SerializeTagL for Bag {
+        fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+            (encode(self).unwrap(), true)
+        }
+    }
+
+    fn bag_new() -> Bag {
+        Bag(vec!["first".to_string()])
+    }
+
+    fn bag_first(bag: &Bag) -> &String {
+        &bag.0[0]
+    }
+
+    // the vector is reallocated, so the strings it held are freed
+    fn bag_refill(bag: &mut Bag) {
+        bag.0.clear();
+        bag.0.shrink_to_fit();
+        bag.0.extend((0..64).map(|i| i.to_string()));
+    }
+
+    #[allow(clippy::ptr_arg)] // registered functions take the type of the resource
+    fn string_len(s: &String) -> usize {
+        s.len()
+    }
+
+    static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+
+    // returns whether another call arrived while this one was running
+    fn counter_rendezvous(counter: &mut Counter) -> bool {
+        ARRIVED.fetch_add(1, Ordering::SeqCst);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while ARRIVED.load(Ordering::SeqCst) < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        ARRIVED.load(Ordering::SeqCst) >= 2
+    }
+
+    fn start_counter_server(policy: PanicPolicy) -> Node {
+        let mut server = UMIEndpoint::new("127.0.0.1:0");
+        server.set_panic_policy(policy);
+        serve(counter_table(), server)
+    }
+
+    fn counter_table() -> RegistryTable {
+        let mut table = RegistryTable::new();
+        register!(table, CounterNew, counter_new, fn() -> Counter, (Counter, ResultOp::Owned));
+        register!(table, CounterBump, counter_bump, fn(&mut Counter) -> bool, (bool, ResultOp::Owned), Counter, &mut Counter);
+        register!(table, CounterRendezvous, counter_rendezvous, fn(&mut Counter) -> bool, (bool, ResultOp::Owned), Counter, &mut Counter);
+        table
+    }
+
+    fn bump(addr: &Node, id: ID) -> Result<ReturnVar, UmiError> {
+        let msg = Message::Invoke(fn_type_name(&counter_bump).to_string(), vec![Variable::MutRefRemote(vec![], addr.clone(), id)], InvokeOp::Owned);
+        invoke(addr, msg)
+    }
+
+    fn new_counter(addr: &Node) -> ID {
+        let msg = Message::Invoke(fn_type_name(&counter_new).to_string(), vec![], InvokeOp::Init);
+        match invoke(addr, msg).unwrap() {
+            ReturnVar::OwnedInit(_, id, _) => id,
+            var => panic!("Unexpected return {:?}", var)
+        }
+    }
+
+    fn serve(table: RegistryTable, server: UMIEndpoint) -> Node {
+        serve_with(table, server, Arc::new(ResourceTable::new()))
+    }
+
+    fn serve_with(table: RegistryTable, mut server: UMIEndpoint, vtable: Arc<ResourceTable>) -> Node {
+        let node = server.node();
+        thread::spawn(move || server.start(table, vtable));
+        node
+    }
+
+    #[test]
+    fn calls_on_different_resources_run_in_parallel() {
+        let addr = start_counter_server(PanicPolicy::Poison);
+        let handles: Vec<_> = (0..2).map(|_| {
+            let addr = addr.clone();
+            let id = new_counter(&addr);
+            thread::spawn(move || {
+                let msg = Message::Invoke(fn_type_name(&counter_rendezvous).to_string(), vec![Variable::MutRefRemote(vec![], addr.clone(), id)], InvokeOp::Owned);
+                match invoke(&addr, msg).unwrap() {
+                    ReturnVar::Owned(s) => decode::<bool>(&s).unwrap(),
+                    var => panic!("Unexpected return {:?}", var)
+                }
+            })
+        }).collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn borrow_entries_are_released_and_invalidated() {
+        let mut table = RegistryTable::new();
+        register!(table, CounterNew, counter_new, fn() -> Counter, (Counter, ResultOp::Owned));
+        register!(table, CounterPeek, counter_peek, fn(&Counter) -> &Counter, (Counter, &Counter, ResultOp::Ref), Counter, &Counter);
+        let vtable = Arc::new(ResourceTable::new());
+        let addr = serve_with(table, UMIEndpoint::new("127.0.0.1:0"), Arc::clone(&vtable));
+        let id = new_counter(&addr);
+        let peek = || {
+            let msg = Message::Invoke(fn_type_name(&counter_peek).to_string(), vec![Variable::RefRemote(vec![], addr.clone(), id)], InvokeOp::Ref);
+            match invoke(&addr, msg).unwrap() {
+                ReturnVar::RefOwned(_, borrow) => borrow,
+                var => panic!("Unexpected return {:?}", var)
+            }
+        };
+
+        let borrow = peek();
+        assert_eq!(vtable.len(), 2);
+        assert!(invoke(&addr, Message::Release(borrow)).is_ok());
+        assert_eq!(vtable.len(), 1);
+        assert!(matches!(invoke(&addr, Message::Release(borrow)), Err(UmiError::Remote(ErrorCode::UnknownResource, _))));
+        // only borrows are released, resources are dropped
+        assert!(matches!(invoke(&addr, Message::Release(id)), Err(UmiError::Remote(ErrorCode::UnknownResource, _))));
+
+        let borrow = peek();
+        assert!(invoke(&addr, Message::Drop(id)).is_ok());
+        assert!(vtable.is_empty());
+        assert!(matches!(invoke(&addr, Message::Release(borrow)), Err(UmiError::Remote(ErrorCode::UnknownResource, _))));
+    }
+
+    #[test]
+    fn mutable_borrows_invalidate_borrow_entries() {
+        let mut table = RegistryTable::new();
+        register!(table, BagNew, bag_new, fn() -> Bag, (Bag, ResultOp::Owned));
+        register!(table, BagFirst, bag_first, fn(&Bag) -> &String, (String, &String, ResultOp::Ref), Bag, &Bag);
+        register!(table, BagRefill, bag_refill, fn(&mut Bag), ((), ResultOp::Owned), Bag, &mut Bag);
+        register!(table, StringLen, string_len, fn(&String) -> usize, (usize, ResultOp::Owned), String, &String);
+        let vtable = Arc::new(ResourceTable::new());
+        let addr = serve_with(table, UMIEndpoint::new("127.0.0.1:0"), Arc::clone(&vtable));
+        let id = match invoke(&addr, Message::Invoke(fn_type_name(&bag_new).to_string(), vec![], InvokeOp::Init)).unwrap() {
+            ReturnVar::OwnedInit(_, id, _) => id,
+            var => panic!("Unexpected return {:?}", var)
+        };
+        let msg = Message::Invoke(fn_type_name(&bag_first).to_string(), vec![Variable::RefRemote(vec![], addr.clone(), id)], InvokeOp::Ref);
+        let first = match invoke(&addr, msg).unwrap() {
+            ReturnVar::RefOwned(_, first) => first,
+            var => panic!("Unexpected return {:?}", var)
+        };
+        let len = || {
+            let msg = Message::Invoke(fn_type_name(&string_len).to_string(), vec![Variable::RefRemote(vec![], addr.clone(), first)], InvokeOp::Owned);
+            invoke(&addr, msg)
+        };
+        assert!(matches!(len(), Ok(ReturnVar::Owned(s)) if decode::<usize>(&s).unwrap() == 5));
+
+        let msg = Message::Invoke(fn_type_name(&bag_refill).to_string(), vec![Variable::MutRefRemote(vec![], addr.clone(), id)], InvokeOp::Owned);
+        assert!(invoke(&addr, msg).is_ok());
+        // the entry pointed to a string the refill freed
+        assert!(matches!(len(), Err(UmiError::Remote(ErrorCode::UnknownResource, _))));
+        assert_eq!(vtable.len(), 1);
+    }
+
+    #[test]
+    fn ids_without_their_token_are_refused() {
+        let addr = start_counter_server(PanicPolicy::Keep);
+        let id = new_counter(&addr);
+        let other = new_counter(&addr);
+        assert_ne!(id.2, other.2);
+
+        // the creation time and sequence number of a resource are not enough to use it
+        let forged = (id.0, id.1, other.2);
+        assert!(matches!(bump(&addr, forged), Err(UmiError::Remote(ErrorCode::UnknownResource, _))));
+        assert!(matches!(invoke(&addr, Message::Drop(forged)), Err(UmiError::Remote(ErrorCode::UnknownResource, _))));
+        assert_eq!(renew(&addr, vec![id, forged]).unwrap(), vec![forged]);
+        assert!(bump(&addr, id).is_ok());
+        assert!(invoke(&addr, Message::Drop(id)).is_ok());
+    }
+
+    #[test]
+    fn error_details_do_not_disclose_tokens() {
+        let addr = start_counter_server(PanicPolicy::Keep);
+        let id = new_counter(&addr);
+        assert!(!format!("{:?}", id).contains(&format!("{:?}", id.2 .0)));
+        let forged = (id.0, id.1, random_token());
+        match invoke(&addr, Message::Drop(forged)) {
+            Err(UmiError::Remote(ErrorCode::UnknownResource, detail)) => assert!(!detail.contains(&format!("{:?}", forged.2 .0))),
+            r => panic!("Unexpected result {:?}", r)
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream, Shutdown};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use rustls::{ClientConfig, ServerConfig, ClientConnection, ServerConnection, StreamOwned, RootCertStore};
+use rustls::crypto::{CryptoProvider, ring};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+
+use crate::transport::{Connection, Listener, Transport};
+use umi_macros::{Address};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsError(pub String);
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tls error: {}", self.0)
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn load_certs(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsError(e.to_string()))?;
+    if certs.is_empty() {
+        return Err(TlsError("no certificate found".to_string()));
+    }
+    Ok(certs)
+}
+
+fn load_key(pem: &[u8]) -> Result<PrivateKeyDer<'static>, TlsError> {
+    rustls_pemfile::private_key(&mut &pem[..])
+        .map_err(|e| TlsError(e.to_string()))?
+        .ok_or_else(|| TlsError("no private key found".to_string()))
+}
+
+fn load_roots(pem: &[u8]) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(pem)? {
+        roots.add(cert).map_err(|e| TlsError(e.to_string()))?;
+    }
+    Ok(roots)
+}
+
+/* The certificate an endpoint presents, optionally with the CA its clients must be signed by */
+#[derive(Clone)]
+pub struct TlsServerConfig(Arc<ServerConfig>);
+
+impl TlsServerConfig {
+    pub fn new(cert_chain_pem: &[u8], key_pem: &[u8]) -> Result<TlsServerConfig, TlsError> {
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| TlsError(e.to_string()))?
+            .with_no_client_auth()
+            .with_single_cert(load_certs(cert_chain_pem)?, load_key(key_pem)?)
+            .map_err(|e| TlsError(e.to_string()))?;
+        Ok(TlsServerConfig(Arc::new(config)))
+    }
+
+    // mutual TLS: clients have to present a certificate signed by client_ca_pem
+    pub fn with_client_auth(cert_chain_pem: &[u8], key_pem: &[u8], client_ca_pem: &[u8]) -> Result<TlsServerConfig, TlsError> {
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(load_roots(client_ca_pem)?), provider())
+            .build()
+            .map_err(|e| TlsError(e.to_string()))?;
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| TlsError(e.to_string()))?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(load_certs(cert_chain_pem)?, load_key(key_pem)?)
+            .map_err(|e| TlsError(e.to_string()))?;
+        Ok(TlsServerConfig(Arc::new(config)))
+    }
+}
+
+/* The CA servers are verified against, optionally with a certificate for mutual TLS.
+ * Clones share the id, which tells apart the connections opened with each configuration
+ */
+#[derive(Clone)]
+pub struct TlsClientConfig(Arc<ClientConfig>, u64);
+
+static NEXT_CLIENT_CONFIG_ID: AtomicU64 = AtomicU64::new(0);
+
+fn next_client_config_id() -> u64 {
+    NEXT_CLIENT_CONFIG_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+impl TlsClientConfig {
+    pub fn new(ca_pem: &[u8]) -> Result<TlsClientConfig, TlsError> {
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| TlsError(e.to_string()))?
+            .with_root_certificates(load_roots(ca_pem)?)
+            .with_no_client_auth();
+        Ok(TlsClientConfig(Arc::new(config), next_client_config_id()))
+    }
+
+    pub fn with_client_cert(ca_pem: &[u8], cert_chain_pem: &[u8], key_pem: &[u8]) -> Result<TlsClientConfig, TlsError> {
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| TlsError(e.to_string()))?
+            .with_root_certificates(load_roots(ca_pem)?)
+            .with_client_auth_cert(load_certs(cert_chain_pem)?, load_key(key_pem)?)
+            .map_err(|e| TlsError(e.to_string()))?;
+        Ok(TlsClientConfig(Arc::new(config), next_client_config_id()))
+    }
+
+    pub fn id(&self) -> u64 {
+        self.1
+    }
+}
+
+static CLIENT_CONFIG: RwLock<Option<TlsClientConfig>> = RwLock::new(None);
+
+thread_local! {
+    static CURRENT_CLIENT_CONFIG: RefCell<Option<TlsClientConfig>> = const { RefCell::new(None) };
+}
+
+/* Sets the configuration used by every proxy of this process to connect to "tls:" addresses */
+pub fn set_client_config(config: TlsClientConfig) {
+    *CLIENT_CONFIG.write().unwrap() = Some(config);
+}
+
+/* Runs f with config as the client configuration of the current thread */
+pub fn with_client_config<R, F: FnOnce() -> R>(config: TlsClientConfig, f: F) -> R {
+    let previous = CURRENT_CLIENT_CONFIG.with(|c| c.replace(Some(config)));
+    struct Restore(Option<TlsClientConfig>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT_CLIENT_CONFIG.with(|c| *c.borrow_mut() = self.0.take());
+        }
+    }
+    let _restore = Restore(previous);
+    f()
+}
+
+/* The configuration the current thread connects to "tls:" addresses with */
+pub fn client_config() -> Option<TlsClientConfig> {
+    CURRENT_CLIENT_CONFIG.with(|c| c.borrow().clone())
+        .or_else(|| CLIENT_CONFIG.read().unwrap().clone())
+}
+
+/* The id of the configuration the current thread connects to "tls:" addresses with */
+pub fn client_config_id() -> Option<u64> {
+    client_config().map(|config| config.id())
+}
+
+impl Connection for StreamOwned<ServerConnection, TcpStream> {
+    fn shutdown(&self) -> io::Result<()> {
+        self.sock.shutdown(Shutdown::Both)
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_timeout(timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.sock.set_nonblocking(nonblocking)
+    }
+
+    fn is_open(&self) -> bool {
+        self.sock.is_open()
+    }
+}
+
+impl Connection for StreamOwned<ClientConnection, TcpStream> {
+    fn shutdown(&self) -> io::Result<()> {
+        self.sock.shutdown(Shutdown::Both)
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_timeout(timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.sock.set_nonblocking(nonblocking)
+    }
+
+    fn is_open(&self) -> bool {
+        self.sock.is_open()
+    }
+}
+
+pub struct TlsListener {
+    listener: TcpListener,
+    config: TlsServerConfig,
+    server_name: String,
+}
+
+impl Listener for TlsListener {
+    fn accept(&self) -> io::Result<(Box<dyn Connection>, String)> {
+        let (stream, peer) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        // the handshake happens on the first reads, while the connection waits for its first request
+        let conn = ServerConnection::new(Arc::clone(&self.config.0)).map_err(io::Error::other)?;
+        Ok((Box::new(StreamOwned::new(conn, stream)), peer.to_string()))
+    }
+
+    fn local_address(&self) -> io::Result<Address> {
+        Ok(Address::Tls(self.listener.local_addr()?, self.server_name.clone()))
+    }
+}
+
+pub fn listen_tls(addr: &Address, config: &TlsServerConfig) -> io::Result<Box<dyn Listener>> {
+    match addr {
+        Address::Tls(addr, server_name) => Ok(Box::new(TlsListener {
+            listener: TcpListener::bind(addr)?,
+            config: config.clone(),
+            server_name: server_name.clone(),
+        })),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("not a tls address: {}", addr)))
+    }
+}
+
+pub struct TlsTransport;
+
+impl Transport for TlsTransport {
+    fn listen(&self, addr: &Address) -> io::Result<Box<dyn Listener>> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} needs a certificate, use UMIEndpoint::with_tls", addr)))
+    }
+
+    fn connect(&self, addr: &Address) -> io::Result<Box<dyn Connection>> {
+        match addr {
+            Address::Tls(addr, server_name) => {
+                let config = client_config()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "no tls client configuration, see umi::tls::set_client_config"))?;
+                let name = ServerName::try_from(server_name.clone())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let conn = ClientConnection::new(config.0, name).map_err(io::Error::other)?;
+                let stream = TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?;
+                let mut tls = StreamOwned::new(conn, stream);
+                // complete the handshake now, so that an untrusted server is reported as a connection failure
+                while tls.conn.is_handshaking() {
+                    tls.conn.complete_io(&mut tls.sock)?;
+                }
+                tls.flush()?;
+                Ok(Box::new(tls))
+            },
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("not a tls address: {}", addr)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::endpoint::{UMIEndpoint, ResourceTable};
+    use crate::message_serialisation::{Message, ReturnVar, InvokeOp, send};
+    use crate::registry::*;
+    use crate::proxy_lib::*;
+    use crate::utils::{fn_type_name};
+    use crate::codec::decode;
+    use crate::transport::{ToAddress};
+    use umi_macros::{Address, Node};
+    use crate::register;
+    use std::any::Any;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn ping() -> bool {
+        true
+    }
+
+    fn serve(table: RegistryTable, server: UMIEndpoint) -> Node {
+        serve_with(table, server, Arc::new(ResourceTable::new()))
+    }
+
+    fn serve_with(table: RegistryTable, mut server: UMIEndpoint, vtable: Arc<ResourceTable>) -> Node {
+        let node = server.node();
+        thread::spawn(move || server.start(table, vtable));
+        node
+    }
+
+    // a CA with a certificate for the endpoint and one for a client, as (ca, server cert, server key, client cert, client key)
+    fn certificates() -> (String, String, String, String, String) {
+        use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let server_key = KeyPair::generate().unwrap();
+        let server = CertificateParams::new(vec!["localhost".to_string()]).unwrap()
+            .signed_by(&server_key, &ca, &ca_key).unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let mut client_params = CertificateParams::new(vec!["client".to_string()]).unwrap();
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+        (ca.pem(), server.pem(), server_key.serialize_pem(), client.pem(), client_key.serialize_pem())
+    }
+
+    fn start_tls_server(config: TlsServerConfig) -> Node {
+        let mut table = RegistryTable::new();
+        register!(table, Ping, ping, fn() -> bool, (bool, ResultOp::Owned));
+        serve(table, UMIEndpoint::with_tls("tls:localhost@127.0.0.1:0", config))
+    }
+
+    fn call_ping<A: ToAddress>(addr: A) -> bool {
+        let msg = Message::Invoke(fn_type_name(&ping).to_string(), vec![], InvokeOp::Owned);
+        match decode(&send(addr, msg).unwrap()).unwrap() {
+            Message::Return(ReturnVar::Owned(s)) => decode::<bool>(&s).unwrap(),
+            _ => panic!("Expecting a return message")
+        }
+    }
+
+    #[test]
+    fn endpoint_serves_tls() {
+        let (ca, cert, key, _, _) = certificates();
+        let addr = start_tls_server(TlsServerConfig::new(cert.as_bytes(), key.as_bytes()).unwrap());
+        assert!(matches!(addr.addrs[0], Address::Tls(_, ref name) if name == "localhost"));
+        let client = TlsClientConfig::new(ca.as_bytes()).unwrap();
+        assert!(with_client_config(client, || call_ping(&addr)));
+
+        // a client trusting another CA refuses the endpoint, whatever connections other clients keep to it
+        let (other_ca, _, _, _, _) = certificates();
+        let client = TlsClientConfig::new(other_ca.as_bytes()).unwrap();
+        let msg = Message::Invoke(fn_type_name(&ping).to_string(), vec![], InvokeOp::Owned);
+        assert!(with_client_config(client, || send(&addr, msg)).is_err());
+    }
+
+    #[test]
+    fn mutual_tls_requires_a_client_certificate() {
+        let (ca, cert, key, client_cert, client_key) = certificates();
+        let server = TlsServerConfig::with_client_auth(cert.as_bytes(), key.as_bytes(), ca.as_bytes()).unwrap();
+        let addr = start_tls_server(server);
+        let client = TlsClientConfig::with_client_cert(ca.as_bytes(), client_cert.as_bytes(), client_key.as_bytes()).unwrap();
+        assert!(with_client_config(client.clone(), || call_ping(&addr)));
+        assert!(with_client_config(client, || call_ping(&addr)));
+
+        let anonymous = TlsClientConfig::new(ca.as_bytes()).unwrap();
+        let msg = Message::Invoke(fn_type_name(&ping).to_string(), vec![], InvokeOp::Owned);
+        assert!(with_client_config(anonymous, || send(&addr, msg)).is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use std::io::{self, Read, Write};
+use std::time::Duration;
+use std::net::{TcpListener, TcpStream, Shutdown, SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::mem::MaybeUninit;
+#[cfg(unix)]
+use socket2::SockRef;
+
+use umi_macros::{Address, Node, NodeId};
+use crate::tls::TlsTransport;
+
+/* A bidirectional byte stream between a client and an endpoint */
+pub trait Connection: Read + Write + Send {
+    fn shutdown(&self) -> io::Result<()>;
+    // None blocks forever
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    // a nonblocking read with nothing to return fails with io::ErrorKind::WouldBlock
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+
+    // false when the peer closed the connection or sent data nobody asked for, checked before reusing an idle connection
+    fn is_open(&self) -> bool {
+        true
+    }
+}
+
+pub trait Listener: Send {
+    // returns the connection and a description of the peer for logging
+    fn accept(&self) -> io::Result<(Box<dyn Connection>, String)>;
+    fn local_address(&self) -> io::Result<Address>;
+}
+
+/* How endpoints listen for and clients open connections */
+pub trait Transport: Sync {
+    fn listen(&self, addr: &Address) -> io::Result<Box<dyn Listener>>;
+    fn connect(&self, addr: &Address) -> io::Result<Box<dyn Connection>>;
+}
+
+pub struct TcpTransport;
+
+#[cfg(unix)]
+pub struct UnixTransport;
+
+/* The transport serving an address, chosen by the kind of the address */
+pub fn transport_for(addr: &Address) -> &'static dyn Transport {
+    match addr {
+        Address::Tcp(_) => &TcpTransport,
+        #[cfg(unix)]
+        Address::Unix(_) => &UnixTransport,
+        #[cfg(not(unix))]
+        Address::Unix(_) => &Unsupported,
+        Address::Tls(..) => &TlsTransport,
+    }
+}
+
+pub fn listen(addr: &Address) -> io::Result<Box<dyn Listener>> {
+    transport_for(addr).listen(addr)
+}
+
+pub fn connect(addr: &Address) -> io::Result<Box<dyn Connection>> {
+    transport_for(addr).connect(addr)
+}
+
+/* Anything that names one or more endpoint addresses */
+pub trait ToAddress {
+    fn to_addresses(&self) -> io::Result<Vec<Address>>;
+
+    // the endpoint named, when it is known whatever its addresses
+    fn node_id(&self) -> Option<NodeId> {
+        None
+    }
+}
+
+impl ToAddress for Node {
+    fn to_addresses(&self) -> io::Result<Vec<Address>> {
+        Ok(self.addrs.clone())
+    }
+
+    fn node_id(&self) -> Option<NodeId> {
+        Some(self.id)
+    }
+}
+
+impl ToAddress for Address {
+    fn to_addresses(&self) -> io::Result<Vec<Address>> {
+        Ok(vec![self.clone()])
+    }
+}
+
+impl ToAddress for SocketAddr {
+    fn to_addresses(&self) -> io::Result<Vec<Address>> {
+        Ok(vec![Address::Tcp(*self)])
+    }
+}
+
+/* "unix:/path" is a Unix domain socket,
+ * "tls:host:port" is resolved as a TLS address whose certificate must be issued to host,
+ * "tls:name@ip:port" gives the certificate name explicitly,
+ * anything else is resolved as a TCP address
+ */
+impl ToAddress for str {
+    fn to_addresses(&self) -> io::Result<Vec<Address>> {
+        if let Some(path) = self.strip_prefix("unix:") {
+            return Ok(vec![Address::Unix(path.into())]);
+        }
+        match self.strip_prefix("tls:") {
+            Some(tls) if tls.contains('@') => self.parse()
+                .map(|addr| vec![addr])
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)),
+            Some(tls) => {
+                let host = match tls.rsplit_once(':') {
+                    Some((host, _)) => host.trim_start_matches('[').trim_end_matches(']'),
+                    None => tls
+                };
+                Ok(tls.to_socket_addrs()?.map(|addr| Address::Tls(addr, host.to_string())).collect())
+            },
+            None => Ok(self.to_socket_addrs()?.map(Address::Tcp).collect())
+        }
+    }
+}
+
+impl ToAddress for String {
+    fn to_addresses(&self) -> io::Result<Vec<Address>> {
+        self.as_str().to_addresses()
+    }
+}
+
+impl<T: ToAddress + ?Sized> ToAddress for &T {
+    fn to_addresses(&self) -> io::Result<Vec<Address>> {
+        (**self).to_addresses()
+    }
+
+    fn node_id(&self) -> Option<NodeId> {
+        (**self).node_id()
+    }
+}
+
+impl<T: ToAddress + ?Sized> ToAddress for &mut T {
+    fn to_addresses(&self) -> io::Result<Vec<Address>> {
+        (**self).to_addresses()
+    }
+
+    fn node_id(&self) -> Option<NodeId> {
+        (**self).node_id()
+    }
+}
+
+impl Connection for TcpStream {
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn is_open(&self) -> bool {
+        if self.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let open = matches!(self.peek(&mut [0; 1]), Err(ref e) if e.kind() == io::ErrorKind::WouldBlock);
+        self.set_nonblocking(false).is_ok() && open
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> io::Result<(Box<dyn Connection>, String)> {
+        let (stream, peer) = TcpListener::accept(self)?;
+        stream.set_nodelay(true)?;
+        Ok((Box::new(stream), peer.to_string()))
+    }
+
+    fn local_address(&self) -> io::Result<Address> {
+        Ok(Address::Tcp(self.local_addr()?))
+    }
+}
+
+impl Transport for TcpTransport {
+    fn listen(&self, addr: &Address) -> io::Result<Box<dyn Listener>> {
+        match addr {
+            Address::Tcp(addr) => Ok(Box::new(TcpListener::bind(addr)?)),
+            _ => Err(wrong_transport(addr))
+        }
+    }
+
+    fn connect(&self, addr: &Address) -> io::Result<Box<dyn Connection>> {
+        match addr {
+            Address::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?; // requests are small and latency bound
+                Ok(Box::new(stream))
+            },
+            _ => Err(wrong_transport(addr))
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    // UnixStream::peek is unstable, the socket is peeked through socket2
+    fn is_open(&self) -> bool {
+        if self.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let open = matches!(SockRef::from(self).peek(&mut [MaybeUninit::uninit(); 1]), Err(ref e) if e.kind() == io::ErrorKind::WouldBlock);
+        self.set_nonblocking(false).is_ok() && open
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    fn accept(&self) -> io::Result<(Box<dyn Connection>, String)> {
+        let (stream, _) = UnixListener::accept(self)?;
+        Ok((Box::new(stream), "unix socket peer".to_string()))
+    }
+
+    fn local_address(&self) -> io::Result<Address> {
+        match self.local_addr()?.as_pathname() {
+            Some(path) => Ok(Address::Unix(path.to_path_buf())),
+            None => Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "unnamed unix socket"))
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixTransport {
+    fn listen(&self, addr: &Address) -> io::Result<Box<dyn Listener>> {
+        match addr {
+            Address::Unix(path) => {
+                // a socket file left behind by a previous endpoint would make bind fail,
+                // one a running endpoint still accepts connections on is not taken over
+                if let Ok(meta) = std::fs::symlink_metadata(path) {
+                    if meta.file_type().is_socket() {
+                        match UnixStream::connect(path) {
+                            Ok(_) => return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("an endpoint is listening on {}", path.display()))),
+                            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => std::fs::remove_file(path)?,
+                            Err(e) => return Err(e)
+                        }
+                    }
+                }
+                Ok(Box::new(UnixListener::bind(path)?))
+            },
+            _ => Err(wrong_transport(addr))
+        }
+    }
+
+    fn connect(&self, addr: &Address) -> io::Result<Box<dyn Connection>> {
+        match addr {
+            Address::Unix(path) => Ok(Box::new(UnixStream::connect(path)?)),
+            _ => Err(wrong_transport(addr))
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Unsupported;
+
+#[cfg(not(unix))]
+impl Transport for Unsupported {
+    fn listen(&self, addr: &Address) -> io::Result<Box<dyn Listener>> {
+        Err(wrong_transport(addr))
+    }
+
+    fn connect(&self, addr: &Address) -> io::Result<Box<dyn Connection>> {
+        Err(wrong_transport(addr))
+    }
+}
+
+fn wrong_transport(addr: &Address) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("no transport for address {}", addr))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::endpoint::{UMIEndpoint, ResourceTable};
+    use crate::message_serialisation::{Message, ReturnVar, InvokeOp, send};
+    use crate::registry::*;
+    use crate::proxy_lib::*;
+    use crate::utils::{fn_type_name};
+    use crate::codec::decode;
+    use umi_macros::{Address, Node};
+    use crate::register;
+    use std::any::Any;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("umi-transport-{}-{}.sock", name, std::process::id()))
+    }
+
+    #[test]
+    fn unix_listen_refuses_a_live_socket_and_replaces_a_stale_one() {
+        let path = socket_path("listen");
+        let addr = Address::Unix(path.clone());
+        let first = listen(&addr).unwrap();
+        assert_eq!(listen(&addr).err().map(|e| e.kind()), Some(io::ErrorKind::AddrInUse));
+        drop(first);
+        // the file of the dropped listener is still there, nobody accepts on it
+        assert!(path.exists());
+        assert!(listen(&addr).is_ok());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn unix_connections_closed_by_the_peer_are_not_open() {
+        let path = socket_path("open");
+        let addr = Address::Unix(path.clone());
+        let listener = listen(&addr).unwrap();
+        let conn = connect(&addr).unwrap();
+        let (peer, _) = listener.accept().unwrap();
+        assert!(conn.is_open());
+        drop(peer);
+        assert!(!conn.is_open());
+        let _ = std::fs::remove_file(path);
+    }
+
+    fn ping() -> bool {
+        true
+    }
+
+    fn start_server<A: ToAddress>(addr: A) -> Node {
+        let mut table = RegistryTable::new();
+        register!(table, Ping, ping, fn() -> bool, (bool, ResultOp::Owned));
+        serve(table, UMIEndpoint::new(addr))
+    }
+
+    fn serve(table: RegistryTable, server: UMIEndpoint) -> Node {
+        serve_with(table, server, Arc::new(ResourceTable::new()))
+    }
+
+    fn serve_with(table: RegistryTable, mut server: UMIEndpoint, vtable: Arc<ResourceTable>) -> Node {
+        let node = server.node();
+        thread::spawn(move || server.start(table, vtable));
+        node
+    }
+
+    fn call_ping<A: ToAddress>(addr: A) -> bool {
+        let msg = Message::Invoke(fn_type_name(&ping).to_string(), vec![], InvokeOp::Owned);
+        match decode(&send(addr, msg).unwrap()).unwrap() {
+            Message::Return(ReturnVar::Owned(s)) => decode::<bool>(&s).unwrap(),
+            _ => panic!("Expecting a return message")
+        }
+    }
+
+    #[test]
+    fn endpoint_serves_unix_domain_sockets() {
+        let path = std::env::temp_dir().join(format!("umi-test-{}.sock", std::process::id()));
+        let addr = start_server(format!("unix:{}", path.display()));
+        assert_eq!(addr.addrs, vec![Address::Unix(path.clone())]);
+        assert!(call_ping(format!("unix:{}", path.display())));
+        assert!(call_ping(&addr));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,5 +1,5 @@
 use std::any::{type_name};
 
-pub fn fn_type_name<T: 'static> (_: &T) -> &'static str {
+pub fn fn_type_name<T> (_: &T) -> &'static str {
     type_name::<T>()
 }
\ No newline at end of file
@@ -1,17 +1,125 @@
 use serde::{Serialize, Deserialize};
 use std::time::SystemTime;
 use std::net::{SocketAddr};
+use std::path::{PathBuf};
+use std::fmt;
+use std::str::FromStr;
 
 // The ID in endpoint will be replaced by this
-pub type ID = (SystemTime, usize);
+// (creation time, sequence number, random token), the token makes IDs unguessable
+pub type ID = (SystemTime, usize, Token);
+
+// A cryptographically random capability, only the holders of a proxy know the token of its resource.
+// Its Debug output leaves the bytes out, so that logs, error messages and proxies do not disclose it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Token(pub [u8; 16]);
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Token(..)")
+    }
+}
+
+// The address of an endpoint, written as "127.0.0.1:3334", "unix:/path/to/socket"
+// or "tls:server_name@127.0.0.1:3334" (the server name is checked against the certificate)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    Tls(SocketAddr, String) // (address, server_name)
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "{}", addr),
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+            Address::Tls(addr, name) => write!(f, "tls:{}@{}", name, addr)
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |e| format!("invalid address {}: {}", s, e);
+        if let Some(path) = s.strip_prefix("unix:") {
+            Ok(Address::Unix(PathBuf::from(path)))
+        } else if let Some(tls) = s.strip_prefix("tls:") {
+            match tls.split_once('@') {
+                Some((name, addr)) => addr.parse().map(|addr| Address::Tls(addr, name.to_string())).map_err(invalid),
+                None => tls.parse().map(|addr: SocketAddr| Address::Tls(addr, addr.ip().to_string())).map_err(invalid)
+            }
+        } else {
+            s.parse().map(Address::Tcp).map_err(invalid)
+        }
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(addr: SocketAddr) -> Self {
+        Address::Tcp(addr)
+    }
+}
+
+// The identity of an endpoint, it does not depend on the addresses the endpoint is reached at
+pub type NodeId = [u8; 16];
+
+// An endpoint holding resources, as carried by proxies: its identity and the addresses to dial it at.
+// Two nodes are the same endpoint when their ids are equal, whatever their addresses
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Node {
+    pub id: NodeId,
+    pub addrs: Vec<Address>, // tried in order
+}
+
+impl Node {
+    pub fn new(id: NodeId, addrs: Vec<Address>) -> Node {
+        Node { id, addrs }
+    }
+
+    // the addresses a client reached the node at are tried before the ones the node advertises
+    pub fn reached_at(mut self, addrs: &[Address]) -> Node {
+        let advertised = std::mem::replace(&mut self.addrs, addrs.to_vec());
+        for addr in advertised {
+            if !self.addrs.contains(&addr) {
+                self.addrs.push(addr);
+            }
+        }
+        self
+    }
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Node {}
+
+impl std::hash::Hash for Node {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addrs: Vec<String> = self.addrs.iter().map(|addr| addr.to_string()).collect();
+        write!(f, "[{}]", addrs.join(", "))
+    }
+}
 
 // The Variable in message_serialisation will be replaced by this
+// Payloads are encoded with the codec selected in umi::codec
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Variable {
-    OwnedLocal(String), // (serialised_local)
-    OwnedRemote(String, SocketAddr, ID), // (serialised_remote, address, id)
-    RefRemote(String, SocketAddr, ID), // (serialised_remote, address, id)
-    MutRefRemote(String, SocketAddr, ID) // (serialised_remote, address, id)
+    OwnedLocal(Vec<u8>), // (serialised_local)
+    OwnedRemote(Vec<u8>, Node, ID), // (serialised_remote, node, id)
+    RefRemote(Vec<u8>, Node, ID), // (serialised_remote, node, id)
+    MutRefRemote(Vec<u8>, Node, ID) // (serialised_remote, node, id)
 }
 
 impl Variable {
@@ -47,7 +155,7 @@ pub trait IsProxyType {
 }
 
 pub trait SerializeTag {
-    fn tagged_string(&self) -> (String, bool); // bool - is_local
+    fn tagged_bytes(&self) -> (Vec<u8>, bool); // bool - is_local
 }
 
 pub trait BorrowRemoteMarker {
@@ -2,11 +2,12 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use quote::{quote, ToTokens};
+use quote::{quote, format_ident, ToTokens};
 use syn::{parse_quote};
 use syn::{parse_macro_input};
-use syn::visit_mut::{self, VisitMut};
 use syn::visit::{self, Visit};
+use syn::visit_mut::{self, VisitMut};
+use std::collections::HashMap;
 
 #[derive(Clone)]
 enum ReturnTypeOptions {
@@ -16,109 +17,11 @@ enum ReturnTypeOptions {
     MutRef
 }
 
-struct ExprVisitor {
-    pub idents: Vec<syn::Ident>,
-}
-
-impl ExprVisitor {
-    pub fn new() -> Self {
-        ExprVisitor {
-            idents: Vec::new(),
-        }
-    }
-}
-
-impl<'ast> Visit<'ast> for ExprVisitor {
-    fn visit_expr(&mut self, node: &'ast syn::Expr) {
-        match node {
-            syn::Expr::Field(f) => {
-                match *(f.base) {
-                    syn::Expr::Path(ref p) => {
-                        if p.path.is_ident("self") {
-                            match f.member {
-                                syn::Member::Named(ref n) => {
-                                    // gather invariants
-                                    if !self.idents.contains(n) {
-                                        self.idents.push(n.clone());
-                                    }
-                                },
-                                _ => {}
-                            }
-                        } else {
-                            // nothing need to be done
-                        }
-                    },
-                    _ => {}
-                }
-            },
-            _ => {}
-        }
-
-        // Delegate to the default impl to visit any nested functions.
-        visit::visit_expr(self, node);
-    }
-}
-
-
-struct ExprReplace;
-
-impl VisitMut for ExprReplace {
-    fn visit_expr_mut(&mut self, node: &mut syn::Expr) {    
-        // The pattern matching on self affects field access self.field -> Local{ref field}
-        // all field accesses are changed into by reference/mutatble reference
-        // in translation:
-        // Replace &self.a with a
-        // Replace self.a with *a
-        match node {
-            syn::Expr::Reference(r) => {
-                match &*(r.expr) {
-                    syn::Expr::Field(f) => {
-                        match *(f.base) {
-                            syn::Expr::Path(ref p) => {
-                                if p.path.is_ident("self") {
-                                    // replace &self.a with a
-                                    match f.member {
-                                        syn::Member::Named(ref n) => {
-                                            let ident = n.clone();
-                                            let new_expr = parse_quote! { #ident };
-                                            *node = new_expr;
-                                            return;
-                                        },
-                                        _ => {}
-                                    }
-                                } // else nothing change
-                            },
-                            _ => {}
-                        }
-                    },
-                    _ => {}
-                }
-            },
-            syn::Expr::Field(f) => {
-                match *(f.base) {
-                    syn::Expr::Path(ref p) => {
-                        if p.path.is_ident("self") {
-                            // replace self.a with *a
-                            match f.member {
-                                syn::Member::Named(ref n) => {
-                                    let ident = n.clone();
-                                    let new_expr = parse_quote! { *#ident };
-                                    *node = new_expr;
-                                    return;
-                                },
-                                _ => {}
-                            }
-                        } // else nothing change
-                    },
-                    _ => {}
-                }
-            },
-            _ => {}
-        }
-
-        // Delegate to the default impl to visit nested expressions.
-        visit_mut::visit_expr_mut(self, node);
-    }
+// the generics of a derived impl with the predicate Self: Serialize, for the impls encoding the proxy
+fn serializable_generics(generics: &syn::Generics) -> syn::Generics {
+    let mut generics = generics.clone();
+    generics.make_where_clause().predicates.push(parse_quote! { Self: Serialize });
+    generics
 }
 
 #[proc_macro_derive(IsLocal)]
@@ -133,8 +36,9 @@ pub fn is_local_derive(input: TokenStream) -> TokenStream {
 
 fn impl_is_local(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
     let gen = quote! {
-        impl IsLocal for #name {
+        impl #impl_generics IsLocal for #name #ty_generics #where_clause {
             fn is_local(&self) -> bool {
                 match self {
                     Self::Remote{..} => { false },
@@ -154,16 +58,18 @@ pub fn to_variable_derive(input: TokenStream) -> TokenStream {
 
 fn impl_to_variable(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
+    let generics = serializable_generics(&ast.generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let gen = quote! {
-        impl ToVariable for #name {
+        impl #impl_generics ToVariable for #name #ty_generics #where_clause {
             fn to_variable(self) -> Variable {
                 let var: Variable;
-                if let #name::Remote(ref addr, ref id, is_owner) = &self {
-                    let remote_borrow = #name::Remote(*addr, *id, Arc::new(AtomicBool::new(false)));
+                if let Self::Remote(ref addr, ref id, is_owner) = &self {
+                    let remote_borrow = Self::Remote(addr.clone(), *id, Arc::new(AtomicBool::new(false)));
                     is_owner.swap(false, Ordering::Relaxed);
-                    var = Variable::OwnedRemote(serde_json::to_string(&remote_borrow).unwrap(), *addr, *id);
+                    var = Variable::OwnedRemote(encode(&remote_borrow).unwrap(), addr.clone(), *id);
                 } else {
-                    var = Variable::OwnedLocal(serde_json::to_string(&self).unwrap());
+                    var = Variable::OwnedLocal(encode(&self).unwrap());
                 }
                 var
             }
@@ -180,13 +86,15 @@ pub fn to_variable_ref_derive(input: TokenStream) -> TokenStream {
 
 fn impl_to_variable_ref(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
+    let generics = serializable_generics(&ast.generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let gen = quote! {
-        impl ToVariableRef for #name {
+        impl #impl_generics ToVariableRef for #name #ty_generics #where_clause {
             fn to_variable(&self) -> Variable {
                 let var: Variable;
-                if let #name::Remote(ref addr, ref id, is_owner) = self {
-                    let remote_borrow = #name::Remote(*addr, *id, Arc::new(AtomicBool::new(false)));
-                    var = Variable::RefRemote(serde_json::to_string(&remote_borrow).unwrap(), *addr, *id);
+                if let Self::Remote(ref addr, ref id, is_owner) = self {
+                    let remote_borrow = Self::Remote(addr.clone(), *id, Arc::new(AtomicBool::new(false)));
+                    var = Variable::RefRemote(encode(&remote_borrow).unwrap(), addr.clone(), *id);
                 } else {
                     // This case should not happen
                     unimplemented!();
@@ -206,13 +114,15 @@ pub fn to_variable_mut_derive(input: TokenStream) -> TokenStream {
 
 fn impl_to_variable_mut(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
+    let generics = serializable_generics(&ast.generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let gen = quote! {
-        impl ToVariableMut for #name {
+        impl #impl_generics ToVariableMut for #name #ty_generics #where_clause {
             fn to_variable(&mut self) -> Variable {
                 let var: Variable;
-                if let #name::Remote(ref addr, ref id, is_owner) = self {
-                    let remote_borrow = #name::Remote(*addr, *id, Arc::new(AtomicBool::new(false)));
-                    var = Variable::MutRefRemote(serde_json::to_string(&remote_borrow).unwrap(), *addr, *id);
+                if let Self::Remote(ref addr, ref id, is_owner) = self {
+                    let remote_borrow = Self::Remote(addr.clone(), *id, Arc::new(AtomicBool::new(false)));
+                    var = Variable::MutRefRemote(encode(&remote_borrow).unwrap(), addr.clone(), *id);
                 } else {
                     // This case should not happen
                     unimplemented!();
@@ -232,14 +142,18 @@ pub fn drop_derive(input: TokenStream) -> TokenStream {
 
 fn impl_drop(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
     let gen = quote! {
-        impl Drop for #name {
+        impl #impl_generics Drop for #name #ty_generics #where_clause {
             fn drop(&mut self) {
                 match self {
                     Self::Remote(addr, id, is_owner) => {
                         if is_owner.load(Ordering::Relaxed) {
                             let msg = Message::Drop(*id);
-                            send(*addr, msg).unwrap();
+                            // panicking in drop would abort an unwinding thread, the remote resource is leaked instead
+                            if let Err(e) = send(&*addr, msg) {
+                                println!("Failed to drop remote resource: {}", e);
+                            }
                         }
                     },
                     _ => {}
@@ -258,10 +172,12 @@ pub fn serialize_tag_derive(input: TokenStream) -> TokenStream {
 
 fn impl_serialize_tag(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
+    let generics = serializable_generics(&ast.generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let gen = quote! {
-        impl SerializeTag for #name {
-            fn tagged_string(&self) -> (String, bool) {
-                let serialised = serde_json::to_string(&self).unwrap();
+        impl #impl_generics SerializeTag for #name #ty_generics #where_clause {
+            fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+                let serialised = encode(self).unwrap();
                 match self {
                     Self::Local{..} => {
                         return (serialised, true)
@@ -284,21 +200,77 @@ pub fn borrow_remote_derive(input: TokenStream) -> TokenStream {
 
 fn impl_borrow_remote(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
     let gen = quote! {
-        impl BorrowRemote for #name {
+        impl #impl_generics BorrowRemote for #name #ty_generics #where_clause {
             fn borrow_remote(&self) -> Self {
                 match self {
                     Self::Local{..} => { panic!("Only suitable for remote"); },
                     Self::Remote(addr, id, _) => {
-                        Self::Remote(*addr, *id, Arc::new(AtomicBool::new(false)))
+                        Self::Remote(addr.clone(), *id, Arc::new(AtomicBool::new(false)))
                     }
                 }
             }
+
+            fn own_remote(self) -> Self {
+                if let Self::Remote(ref addr, id, _) = self {
+                    let owner = Arc::new(AtomicBool::new(true));
+                    hold_lease(addr, id, &owner);
+                    Self::Remote(addr.clone(), id, owner)
+                } else {
+                    self
+                }
+            }
+        }
+    };
+    gen.into()
+}
+
+
+#[proc_macro_derive(Proxy)]
+pub fn proxy_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_proxy(&ast)
+}
+
+fn impl_proxy(ast: &syn::DeriveInput) -> TokenStream {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let gen = quote! {
+        impl #impl_generics Proxy for #name #ty_generics #where_clause {
+            fn construct_remote(node: Node, id: ID, is_owner: Arc<AtomicBool>) -> Self {
+                Self::Remote(node, id, is_owner)
+            }
         }
     };
     gen.into()
 }
 
+#[proc_macro_derive(Lift)]
+pub fn lift_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_lift(&ast)
+}
+
+fn impl_lift(ast: &syn::DeriveInput) -> TokenStream {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let gen = quote! {
+        impl #impl_generics Lift for #name #ty_generics #where_clause {
+            fn lift(self) -> Self {
+                if let Self::Remote(ref addr, id, ref is_owner) = self {
+                    is_owner.store(false, Ordering::Relaxed); // the decoded copy must not drop the resource
+                    let owner = Arc::new(AtomicBool::new(true));
+                    hold_lease(addr, id, &owner);
+                    Self::Remote(addr.clone(), id, owner)
+                } else {
+                    self
+                }
+            }
+        }
+    };
+    gen.into()
+}
 
 #[proc_macro_derive(IsProxyType, attributes(is_lifted_or_not))]
 pub fn is_proxy_type_derive(input: TokenStream) -> TokenStream {
@@ -307,11 +279,13 @@ pub fn is_proxy_type_derive(input: TokenStream) -> TokenStream {
 }
 fn impl_is_proxy_type(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
-    let attrs = &ast.attrs;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    // the type may carry other attributes
+    let attrs: Vec<&syn::Attribute> = ast.attrs.iter().filter(|attr| attr.path.is_ident("is_lifted_or_not")).collect();
     if attrs.len() != 1 {
         panic!("Expected exactly one attribute");
     } else {
-        let attr = &attrs[0];
+        let attr = attrs[0];
         if attr.path.segments.len() > 1 {
             panic!("Expected exactly one attribute");
         } else {
@@ -325,7 +299,7 @@ fn impl_is_proxy_type(ast: &syn::DeriveInput) -> TokenStream {
                     panic!("Expected attribute to be either (lifted) or (not_lifted)");
                 } else if attr_tokens_str == "(lifted)" {
                     let gen = quote! {
-                        impl IsProxyType for #name {
+                        impl #impl_generics IsProxyType for #name #ty_generics #where_clause {
                             fn is_proxy_type(&self) -> bool {
                                 true
                             }
@@ -334,7 +308,7 @@ fn impl_is_proxy_type(ast: &syn::DeriveInput) -> TokenStream {
                     return gen.into();
                 } else {
                     let gen = quote! {
-                        impl IsProxyType for #name {
+                        impl #impl_generics IsProxyType for #name #ty_generics #where_clause {
                             fn is_proxy_type(&self) -> bool {
                                 false
                             }
@@ -347,6 +321,132 @@ fn impl_is_proxy_type(ast: &syn::DeriveInput) -> TokenStream {
     }
 }
 
+/* The implementation of a trait derived by a proxied struct for its proxy.
+ * Local values behave as the struct does, and proxies of the same resource are equal
+ */
+fn gen_proxy_aware_impl(derive: &str, s: &syn::ItemStruct) -> impl ToTokens {
+    let name = &s.ident;
+    let proxy = format_ident!("{}Proxy", name);
+    let trait_ident = format_ident!("{}", derive);
+    let trait_path: syn::Path = match derive {
+        "Debug" => parse_quote! { std::fmt::Debug },
+        "Hash" => parse_quote! { std::hash::Hash },
+        _ => parse_quote! { #trait_ident }
+    };
+    let mut generics = s.generics.clone();
+    let (_, ty_generics, _) = s.generics.split_for_impl();
+    generics.make_where_clause().predicates.push(parse_quote! { #name #ty_generics: #trait_path });
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let body = match derive {
+        "Debug" => quote! {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    Self::Local(value) => std::fmt::Debug::fmt(value, f),
+                    Self::Remote(node, id, _) => {
+                        f.debug_tuple(concat!(stringify!(#proxy), "::Remote")).field(&format_args!("{}", node)).field(id).finish()
+                    }
+                }
+            }
+        },
+        "PartialEq" => quote! {
+            fn eq(&self, other: &Self) -> bool {
+                match (self, other) {
+                    (Self::Local(value), Self::Local(other_value)) => value == other_value,
+                    (Self::Remote(node, id, _), Self::Remote(other_node, other_id, _)) => node == other_node && id == other_id,
+                    _ => false
+                }
+            }
+        },
+        "Eq" => quote! {},
+        "Hash" => quote! {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                match self {
+                    Self::Local(value) => std::hash::Hash::hash(value, state),
+                    Self::Remote(node, id, _) => {
+                        std::hash::Hash::hash(node, state);
+                        std::hash::Hash::hash(id, state);
+                    }
+                }
+            }
+        },
+        // local values come before proxies, which are ordered by their resource
+        "PartialOrd" => quote! {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                match (self, other) {
+                    (Self::Local(value), Self::Local(other_value)) => PartialOrd::partial_cmp(value, other_value),
+                    (Self::Remote(node, id, _), Self::Remote(other_node, other_id, _)) => Some((node.id, id).cmp(&(other_node.id, other_id))),
+                    (Self::Local(..), Self::Remote(..)) => Some(std::cmp::Ordering::Less),
+                    (Self::Remote(..), Self::Local(..)) => Some(std::cmp::Ordering::Greater)
+                }
+            }
+        },
+        "Ord" => quote! {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                match (self, other) {
+                    (Self::Local(value), Self::Local(other_value)) => Ord::cmp(value, other_value),
+                    (Self::Remote(node, id, _), Self::Remote(other_node, other_id, _)) => (node.id, id).cmp(&(other_node.id, other_id)),
+                    (Self::Local(..), Self::Remote(..)) => std::cmp::Ordering::Less,
+                    (Self::Remote(..), Self::Local(..)) => std::cmp::Ordering::Greater
+                }
+            }
+        },
+        "Default" => quote! {
+            fn default() -> Self {
+                Self::Local(Default::default())
+            }
+        },
+        _ => unreachable!()
+    };
+    quote! {
+        impl #impl_generics #trait_path for #proxy #ty_generics #where_clause {
+            #body
+        }
+    }
+}
+
+// the generics of the proxies of references, which borrow for 'umi
+fn view_generics(generics: &syn::Generics) -> syn::Generics {
+    let mut generics = generics.clone();
+    generics.params.insert(0, parse_quote! { 'umi });
+    generics
+}
+
+/* Removes #[serde(borrow)] from the fields of a proxied struct, whose values are decoded into resources and proxies that own them.
+ * A Cow<'a, str> field is then decoded as an owned string, so that the struct implements DeserializeOwned for any 'a
+ */
+fn own_borrowed_fields(s: &mut syn::ItemStruct) -> syn::Result<()> {
+    for field in s.fields.iter_mut() {
+        if let syn::Type::Reference(ref r) = field.ty {
+            return Err(syn::Error::new(r.and_token.span, "proxy_me error: a proxied struct owns its fields, use Cow<'a, T> instead of a reference"));
+        }
+        let mut attrs = Vec::new();
+        for attr in field.attrs.drain(..) {
+            if !attr.path.is_ident("serde") {
+                attrs.push(attr);
+                continue;
+            }
+            let nested = attr.parse_args_with(syn::punctuated::Punctuated::<syn::NestedMeta, syn::Token![,]>::parse_terminated)?;
+            let kept: Vec<syn::NestedMeta> = nested.into_iter().filter(|meta| match meta {
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) => !p.is_ident("borrow"),
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) => !nv.path.is_ident("borrow"),
+                _ => true
+            }).collect();
+            if !kept.is_empty() {
+                attrs.push(parse_quote! { #[serde(#(#kept),*)] });
+            }
+        }
+        field.attrs = attrs;
+    }
+    Ok(())
+}
+
+/* Keeps a struct as it is and generates its proxy, e.g. StudentRecordProxy for StudentRecord,
+ * which is either a local value of the struct or stands for a resource on an endpoint.
+ * StudentRecordRef and StudentRecordMut are the proxies of references, returned by proxy methods returning references.
+ * The proxy gets the annotated methods of impl blocks with #[umi_register].
+ * An enum gets a Remote variant instead, and is its own proxy.
+ * The struct may borrow where it is used locally, but its fields marked #[serde(borrow)] are decoded as owned values
+ */
 #[proc_macro_attribute]
 pub fn proxy_me(args: TokenStream, input: TokenStream) -> TokenStream {
     let mut ty = parse_macro_input!(input as syn::Item);
@@ -354,32 +454,181 @@ pub fn proxy_me(args: TokenStream, input: TokenStream) -> TokenStream {
 
     match &mut ty {
         syn::Item::Struct(s) => {
-            let struct_ident = s.ident.clone();
-            let visibility = s.vis.clone();
-            let fields = s.fields.clone();
-            let mut field_tys: Vec<syn::Type> = Vec::new();
-            let mut field_names: Vec<syn::Ident> = Vec::new();
-            for f in fields.iter() {
-                field_tys.push(f.ty.clone());
-                field_names.push(f.ident.clone().unwrap());
+            if let Err(e) = own_borrowed_fields(s) {
+                return e.to_compile_error().into();
+            }
+            let name = s.ident.clone();
+            let visibility = &s.vis;
+            let proxy = format_ident!("{}Proxy", name);
+            let proxy_ref = format_ident!("{}Ref", name);
+            let proxy_mut = format_ident!("{}Mut", name);
+            let generics = &s.generics;
+            let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+            let views = view_generics(generics);
+            let (view_impl_generics, view_ty_generics, _) = views.split_for_impl();
+            let serializable = serializable_generics(generics);
+            let (serializable_impl_generics, _, serializable_where_clause) = serializable.split_for_impl();
+            let mut derived: Vec<String> = Vec::new();
+            let mut proxy_aware_impls = Vec::new();
+            for attr in &s.attrs {
+                if attr.path.is_ident("derive") {
+                    let paths = match attr.parse_args_with(syn::punctuated::Punctuated::<syn::Path, syn::Token![,]>::parse_terminated) {
+                        Ok(paths) => paths,
+                        Err(e) => return e.to_compile_error().into()
+                    };
+                    for path in paths {
+                        let derive = path.segments.last().unwrap().ident.to_string();
+                        if let "Debug" | "PartialEq" | "Eq" | "Hash" | "PartialOrd" | "Ord" | "Default" = derive.as_str() {
+                            proxy_aware_impls.push(gen_proxy_aware_impl(&derive, s));
+                        }
+                        derived.push(derive);
+                    }
+                }
             }
+            // values are encoded and cloned into proxies
+            let missing: Vec<syn::Ident> = ["Serialize", "Deserialize", "Clone"].iter()
+                .filter(|derive| !derived.iter().any(|d| d == *derive))
+                .map(|derive| format_ident!("{}", derive))
+                .collect();
+            let struct_derives = if missing.is_empty() {
+                quote! {}
+            } else {
+                quote! { #[derive(#(#missing),*)] }
+            };
+            let item = &s;
             let gen = quote! {
-                #[derive(IsLocal, ToVariable, ToVariableRef, ToVariableMut, Serialize, Deserialize, Clone, DropMarker, IsProxyType, SerializeTag, BorrowRemoteMarker)]
+                #struct_derives
+                #item
+
+                #[doc = concat!("A proxy of [`", stringify!(#name), "`], either a local value or a resource on an endpoint")]
+                #[derive(IsLocal, ToVariable, ToVariableRef, ToVariableMut, Serialize, Deserialize, Clone, DropMarker, IsProxyType, SerializeTag, BorrowRemoteMarker, Proxy, Lift)]
                 #[is_lifted_or_not(lifted)]
-                #visibility enum #struct_ident {
-                    Local{#(#field_names: #field_tys),*},
-                    Remote(SocketAddr, ID, Arc<AtomicBool>)
+                #visibility enum #proxy #generics #where_clause {
+                    Local(#name #ty_generics),
+                    Remote(Node, ID, Arc<AtomicBool>)
+                }
+
+                #[doc = concat!("A reference returned by a method of [`", stringify!(#proxy), "`]")]
+                #visibility enum #proxy_ref #views #where_clause {
+                    Local(&'umi #name #ty_generics),
+                    Remote(#proxy #ty_generics, Option<RemoteBorrow>)
+                }
+
+                #[doc = concat!("A mutable reference returned by a method of [`", stringify!(#proxy), "`]")]
+                #visibility enum #proxy_mut #views #where_clause {
+                    Local(&'umi mut #name #ty_generics),
+                    Remote(#proxy #ty_generics, Option<RemoteBorrow>)
+                }
+
+                impl #impl_generics Proxied for #name #ty_generics #where_clause {
+                    type Proxy = #proxy #ty_generics;
+                    type Ref<'umi> = #proxy_ref #view_ty_generics where Self: 'umi;
+                    type Mut<'umi> = #proxy_mut #view_ty_generics where Self: 'umi;
+
+                    fn remote_ref<'umi>(proxy: Self::Proxy, borrow: Option<RemoteBorrow>) -> Self::Ref<'umi> {
+                        #proxy_ref::Remote(proxy, borrow)
+                    }
+
+                    fn remote_mut<'umi>(proxy: Self::Proxy, borrow: Option<RemoteBorrow>) -> Self::Mut<'umi> {
+                        #proxy_mut::Remote(proxy, borrow)
+                    }
+                }
+
+                impl #impl_generics From<#name #ty_generics> for #proxy #ty_generics #where_clause {
+                    fn from(value: #name #ty_generics) -> Self {
+                        Self::Local(value)
+                    }
+                }
+
+                impl #view_impl_generics From<&'umi #name #ty_generics> for #proxy_ref #view_ty_generics #where_clause {
+                    fn from(value: &'umi #name #ty_generics) -> Self {
+                        Self::Local(value)
+                    }
+                }
+
+                impl #view_impl_generics From<&'umi mut #name #ty_generics> for #proxy_mut #view_ty_generics #where_clause {
+                    fn from(value: &'umi mut #name #ty_generics) -> Self {
+                        Self::Local(value)
+                    }
+                }
+
+                // the struct is passed and returned by copy
+                impl #serializable_impl_generics ToVariable for #name #ty_generics #serializable_where_clause {
+                    fn to_variable(self) -> Variable {
+                        Variable::OwnedLocal(encode(&self).unwrap())
+                    }
+                }
+
+                impl #serializable_impl_generics SerializeTag for #name #ty_generics #serializable_where_clause {
+                    fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+                        (encode(self).unwrap(), true)
+                    }
+                }
+
+                impl #impl_generics BorrowRemote for #name #ty_generics #where_clause {
+                    fn borrow_remote(&self) -> Self {
+                        panic!("This should never be called");
+                    }
+                }
+
+                impl #impl_generics Lift for #name #ty_generics #where_clause {
+                    fn lift(self) -> Self {
+                        self
+                    }
                 }
+
+                #(#proxy_aware_impls)*
             };
-            ty = syn::parse(gen.into()).unwrap();
-            ty.into_token_stream().into()
+            gen.into()
         },
         syn::Item::Enum(ref mut e) => {
-            let gen = quote! { Remote(SocketAddr, ID, Arc<AtomicBool>) };
+            let gen = quote! { Remote(Node, ID, Arc<AtomicBool>) };
             e.variants.push(syn::parse(gen.into()).unwrap());
+            let name = &e.ident;
+            let (impl_generics, ty_generics, where_clause) = e.generics.split_for_impl();
             let result = quote!{
-                #[derive(IsLocal, ToVariable, ToVariableRef, ToVariableMut, Serialize, Deserialize, Clone)]
-                #ty
+                #[derive(IsLocal, ToVariable, ToVariableRef, ToVariableMut, Serialize, Deserialize, Clone, DropMarker, Proxy, Lift)]
+                #e
+
+                // the variants other than Remote are local values
+                impl #impl_generics SerializeTag for #name #ty_generics #where_clause {
+                    fn tagged_bytes(&self) -> (Vec<u8>, bool) {
+                        (encode(self).unwrap(), !matches!(self, Self::Remote(..)))
+                    }
+                }
+
+                impl #impl_generics BorrowRemote for #name #ty_generics #where_clause {
+                    fn borrow_remote(&self) -> Self {
+                        match self {
+                            Self::Remote(addr, id, _) => Self::Remote(addr.clone(), *id, Arc::new(AtomicBool::new(false))),
+                            _ => panic!("Only suitable for remote")
+                        }
+                    }
+
+                    fn own_remote(self) -> Self {
+                        if let Self::Remote(ref addr, id, _) = self {
+                            let owner = Arc::new(AtomicBool::new(true));
+                            hold_lease(addr, id, &owner);
+                            Self::Remote(addr.clone(), id, owner)
+                        } else {
+                            self
+                        }
+                    }
+                }
+
+                impl #impl_generics Proxied for #name #ty_generics #where_clause {
+                    type Proxy = Self;
+                    type Ref<'umi> = RemoteRef<'umi, Self> where Self: 'umi;
+                    type Mut<'umi> = RemoteMut<'umi, Self> where Self: 'umi;
+
+                    fn remote_ref<'umi>(proxy: Self, borrow: Option<RemoteBorrow>) -> Self::Ref<'umi> {
+                        RemoteRef::Remote(proxy, borrow)
+                    }
+
+                    fn remote_mut<'umi>(proxy: Self, borrow: Option<RemoteBorrow>) -> Self::Mut<'umi> {
+                        RemoteMut::Remote(proxy, borrow)
+                    }
+                }
             };
             result.into_token_stream().into()
         },
@@ -387,116 +636,152 @@ pub fn proxy_me(args: TokenStream, input: TokenStream) -> TokenStream {
     }
 }
 
+/* Marks a constructor to register, see umi_register. The server runs it as it is written,
+ * and remote! initialises a resource with it
+ */
 #[proc_macro_attribute]
 pub fn umi_init(args: TokenStream, input: TokenStream) -> TokenStream {
-    let mut func_block = parse_macro_input!(input as syn::Item);
+    let func = parse_macro_input!(input as syn::ImplItemMethod);
     assert!(args.is_empty());
+    func.into_token_stream().into()
+}
 
-    match &func_block {
-        syn::Item::Fn(func) => {
-            let mut pos = 0;
-            let mut gen = None;
-            for stmt in func.block.stmts.iter() {
-                match stmt {
-                    syn::Stmt::Expr(expr) => {
-                        match expr {
-                            syn::Expr::Struct(s) => {
-                                let rty = s.path.segments[0].ident.clone();
-                                let mut args: Vec<syn::Expr> = Vec::new();
-                                let mut field_names: Vec<syn::Ident> = Vec::new();
-                                for f in s.fields.iter() {
-                                    match &f.member {
-                                        syn::Member::Named(ident) => {
-                                            field_names.push(ident.clone());
-                                        },
-                                        _ => {unimplemented!{}}
-                                    }
-                                    match &f.expr {
-                                        syn::Expr::Path(..) => {
-                                            args.push(f.expr.clone());
-                                        },
-                                        syn::Expr::Call(..) => {
-                                            args.push(f.expr.clone());
-                                        },
-                                        _ => {
-                                            // maybe there are more possible expressions?
-                                            panic!("umi_init error: Not a path type")
-                                        }
-                                    }
-                                }
-                                let gen_stmt = quote! {
-                                    #rty::Local{#(#field_names: #args),*}
-                                };
-                                gen = Some(gen_stmt);
-                                break;
-                            },
-                            _ => {}
-                        }
-                    },
-                    _ => {}
+/* The options of a method: `false` - the return type is not a proxy type, `fallible` - the proxy method returns Result<T, UmiError>,
+ * and the instantiations of a generic method to register, e.g. `<String>` for contains<Q>
+ */
+#[derive(Default)]
+struct MethodOptions {
+    return_lifted: bool,
+    fallible: bool,
+    instances: Vec<syn::AngleBracketedGenericArguments>,
+}
+
+impl syn::parse::Parse for MethodOptions {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut options = MethodOptions { return_lifted: true, ..Default::default() };
+        while !input.is_empty() {
+            if input.peek(syn::LitBool) {
+                let lit: syn::LitBool = input.parse()?;
+                if lit.value {
+                    return Err(syn::Error::new(lit.span, "unknown option true"));
                 }
-                pos += 1;
+                options.return_lifted = false;
+            } else if input.peek(syn::Token![<]) {
+                options.instances.push(input.parse()?);
+            } else {
+                let option: syn::Ident = input.parse()?;
+                if option != "fallible" {
+                    return Err(syn::Error::new(option.span(), format!("unknown option {}", option)));
+                }
+                options.fallible = true;
             }
-            match &mut func_block {
-                syn::Item::Fn(ref mut func) => {
-                    match gen {
-                        Some(g) => {
-                            // replace the statement for constructing struct with the generated one
-                            let expr: syn::Expr = syn::parse(g.into()).unwrap();
-                            func.block.stmts[pos] = syn::Stmt::Expr(expr);
-                            func_block.into_token_stream().into()
-                        },
-                        None => {panic!("umi_init error: Invalid generated stmt")}
-                    }
-                },
-                _ => {panic!("umi_init error: Not a function")} // should not reach here
+            if !input.is_empty() {
+                input.parse::<syn::Token![,]>()?;
             }
-        },
-        _ => {panic!("umi_init error: Not a function")} // should not reach here
+        }
+        Ok(options)
+    }
+}
+
+fn method_options(attr: &syn::Attribute) -> MethodOptions {
+    if attr.tokens.is_empty() {
+        return MethodOptions { return_lifted: true, ..Default::default() };
+    }
+    attr.parse_args().unwrap_or_else(|e| panic!("umi_struct_method error: {}", e))
+}
+
+// how generated code unwraps a Result: panicking, or returning the error from a fallible method
+fn gen_unwrap(fallible: bool) -> impl ToTokens {
+    if fallible {
+        quote! { ? }
+    } else {
+        quote! { .unwrap() }
+    }
+}
+
+// how generated code reacts to an unexpected return value
+fn gen_invalid_return(fallible: bool, msg: &str) -> impl ToTokens {
+    if fallible {
+        quote! { return Err(UmiError::InvalidResponse(#msg.to_string())) }
+    } else {
+        quote! { panic!(#msg) }
+    }
+}
+
+// a generic type named in an expression or a pattern needs the turbofish, e.g. Cache::<K, V>::Remote
+fn turbofish(mut ty: syn::TypePath) -> syn::TypePath {
+    for segment in ty.path.segments.iter_mut() {
+        if let syn::PathArguments::AngleBracketed(ref mut args) = segment.arguments {
+            args.colon2_token = Some(Default::default());
+        }
+    }
+    ty
+}
+
+// the type parameters of a generic method, which name it with the turbofish
+fn gen_method_params(sig: &syn::Signature) -> impl ToTokens {
+    let params: Vec<&syn::Ident> = sig.generics.params.iter().filter_map(|p| match p {
+        syn::GenericParam::Type(t) => Some(&t.ident),
+        syn::GenericParam::Const(c) => Some(&c.ident),
+        syn::GenericParam::Lifetime(_) => None
+    }).collect();
+    if params.is_empty() {
+        quote! {}
+    } else {
+        quote! { ::<#(#params),*> }
     }
 }
 
+// the method named by the generated invocation, a generic method is named with its type parameters
+fn gen_method_path(ty: impl ToTokens, sig: &syn::Signature) -> impl ToTokens {
+    let ident = &sig.ident;
+    let params = gen_method_params(sig);
+    quote! { #ty::#ident #params }
+}
+
 // gnerating the deserialisation of the return value for the remote case
-fn gen_remote_match_expr(op: ReturnTypeOptions, ident: Option<syn::TypePath>, return_type_lifted: bool) -> Option<syn::ExprMatch> {
+fn gen_remote_match_expr(op: ReturnTypeOptions, ident: Option<syn::TypePath>, return_type_lifted: bool, fallible: bool) -> Option<syn::ExprMatch> {
+    let ident = ident.map(turbofish);
+    let unwrap = gen_unwrap(fallible);
+    let wrong_return = gen_invalid_return(fallible, "Wrong return value");
     match op {
         ReturnTypeOptions::Default => {
             None
         },
         ReturnTypeOptions::Owned => {
             let ty = ident.unwrap();
+            // the endpoint runs a fallible method locally, which returns the value wrapped in Ok
+            let decode_owned = if fallible {
+                quote! { decode::<Result<#ty, UmiError>>(&s)?? }
+            } else {
+                quote! { decode(&s).unwrap() }
+            };
             let gen;
             if return_type_lifted {
                 gen = quote! {
-                    match deserialised {
-                        Message::Return(v) => {
-                            match v {
-                                ReturnVar::Owned(s) => {
-                                    let result: #ty = serde_json::from_str(&s).unwrap();
-                                    if let #ty::Remote(addr, id, _) = result {
-                                        #ty::Remote(addr, id, Arc::new(AtomicBool::new(true)))
-                                    } else {
-                                        result
-                                    }
-                                },
-                                _ => {panic!("Wrong return value")}
+                    match returned {
+                        ReturnVar::Owned(s) => {
+                            let result: #ty = #decode_owned;
+                            if let #ty::Remote(ref addr, id, ref is_owner) = result {
+                                is_owner.store(false, Ordering::Relaxed); // the decoded copy must not drop the resource
+                                let owner = Arc::new(AtomicBool::new(true));
+                                hold_lease(addr, id, &owner);
+                                #ty::Remote(addr.clone(), id, owner)
+                            } else {
+                                result
                             }
                         },
-                        _ => {panic!("Invalid return message")}
+                        _ => {#wrong_return}
                     }
                 };
             } else {
                 gen = quote! {
-                    match deserialised {
-                        Message::Return(v) => {
-                            match v {
-                                ReturnVar::Owned(s) => {
-                                    let result: #ty = serde_json::from_str(&s).unwrap();
-                                    result
-                                },
-                                _ => {panic!("Wrong return value")}
-                            }
+                    match returned {
+                        ReturnVar::Owned(s) => {
+                            let result: #ty = #decode_owned;
+                            result
                         },
-                        _ => {panic!("Invalid return message")}
+                        _ => {#wrong_return}
                     }
                 };
             }
@@ -507,33 +792,25 @@ fn gen_remote_match_expr(op: ReturnTypeOptions, ident: Option<syn::TypePath>, re
         },
         ReturnTypeOptions::Ref => {
             let ty = ident.unwrap();
+            // the guard releases the borrow entry on the endpoint when dropped
             let gen = quote! {
-                match deserialised {
-                    Message::Return(v) => {
-                        match v {
-                            ReturnVar::RefOwned(addr, id)=> {
-                                let remote: Box<dyn Any> = Box::new(#ty::Remote(addr, id, Arc::new(AtomicBool::new(true))));
-                                unsafe {
-                                    REFS.push(remote); // hold the value in global varible for a longer lifetime
-                                    REFS.last().unwrap().downcast_ref::<#ty>().unwrap()
-                                }
-                            },
-                            ReturnVar::RefBorrow(serialised) => {
-                                let deserialised: #ty = serde_json::from_str(&serialised).unwrap();
-                                if let #ty::Remote(addr, id, _) = deserialised {
-                                    let remote: Box<dyn Any> = Box::new(#ty::Remote(addr, id, Arc::new(AtomicBool::new(false))));
-                                    unsafe {
-                                        REFS.push(remote); // hold the value in global varible for a longer lifetime
-                                        REFS.last().unwrap().downcast_ref::<#ty>().unwrap()
-                                    }
-                                } else {
-                                    panic!("Wrong return value")
-                                }
-                            },
-                            _ => {panic!("Wrong return value")}
+                match returned {
+                    ReturnVar::RefOwned(node, id) => {
+                        // the entry lives on the node of self, reached at the addresses of self
+                        let node = node.reached_at(&addr.addrs);
+                        let remote = #ty::Remote(node.clone(), id, Arc::new(AtomicBool::new(false)));
+                        RemoteRef::Remote(remote, Some(RemoteBorrow::new(node, id)))
+                    },
+                    ReturnVar::RefBorrow(serialised) => {
+                        let deserialised: #ty = decode(&serialised)#unwrap;
+                        if let #ty::Remote(_, _, ref is_owner) = deserialised {
+                            is_owner.store(false, Ordering::Relaxed);
+                            RemoteRef::Remote(deserialised, None)
+                        } else {
+                            #wrong_return
                         }
                     },
-                    _ => {panic!("Invalid return message")}
+                    _ => {#wrong_return}
                 }
             };
             let expr: syn::ExprMatch = syn::parse(gen.into()).unwrap();
@@ -542,32 +819,23 @@ fn gen_remote_match_expr(op: ReturnTypeOptions, ident: Option<syn::TypePath>, re
         ReturnTypeOptions::MutRef => {
             let ty = ident.unwrap();
             let gen = quote!{
-                match deserialised {
-                    Message::Return(v) => {
-                        match v {
-                            ReturnVar::MutRefOwned(addr, id) => {
-                                let remote: Box<dyn Any> = Box::new(#ty::Remote(addr, id, Arc::new(AtomicBool::new(true))));
-                                unsafe {
-                                    REFS.push(remote); // hold the value in global varible for a longer lifetime
-                                    REFS.last_mut().unwrap().downcast_mut::<#ty>().unwrap()
-                                }
-                            },
-                            ReturnVar::MutRefBorrow(serialised) => {
-                                let deserialised: #ty = serde_json::from_str(&serialised).unwrap();
-                                if let #ty::Remote(addr, id, _) = deserialised {
-                                    let remote: Box<dyn Any> = Box::new(#ty::Remote(addr, id, Arc::new(AtomicBool::new(false))));
-                                    unsafe {
-                                        REFS.push(remote); // hold the value in global varible for a longer lifetime
-                                        REFS.last_mut().unwrap().downcast_mut::<#ty>().unwrap()
-                                    }
-                                } else {
-                                    panic!("Wrong return value 1")
-                                }
-                            },
-                            _ => {panic!("Wrong return value")}
+                match returned {
+                    ReturnVar::MutRefOwned(node, id) => {
+                        // the entry lives on the node of self, reached at the addresses of self
+                        let node = node.reached_at(&addr.addrs);
+                        let remote = #ty::Remote(node.clone(), id, Arc::new(AtomicBool::new(false)));
+                        RemoteMut::Remote(remote, Some(RemoteBorrow::new(node, id)))
+                    },
+                    ReturnVar::MutRefBorrow(serialised) => {
+                        let deserialised: #ty = decode(&serialised)#unwrap;
+                        if let #ty::Remote(_, _, ref is_owner) = deserialised {
+                            is_owner.store(false, Ordering::Relaxed);
+                            RemoteMut::Remote(deserialised, None)
+                        } else {
+                            #wrong_return
                         }
                     },
-                    _ => {panic!("Invalid return message")}
+                    _ => {#wrong_return}
                 }
             };
             let expr: syn::ExprMatch = syn::parse(gen.into()).unwrap();
@@ -576,496 +844,784 @@ fn gen_remote_match_expr(op: ReturnTypeOptions, ident: Option<syn::TypePath>, re
     }
 }
 
+// a method returning a reference returns a guard instead, which releases the borrow on the endpoint when dropped
+fn gen_guard_return(sig: &mut syn::Signature) -> Option<syn::Ident> {
+    if let syn::ReturnType::Type(_, ref ty) = sig.output {
+        if let syn::Type::Reference(ref r) = **ty {
+            let elem = &r.elem;
+            let lifetime = match &r.lifetime {
+                Some(l) => quote! { #l },
+                None => quote! { '_ }
+            };
+            let guard = match r.mutability {
+                Some(_) => format_ident!("RemoteMut"),
+                None => format_ident!("RemoteRef")
+            };
+            sig.output = parse_quote! { -> #guard<#lifetime, #elem> };
+            return Some(guard);
+        }
+    }
+    None
+}
+
+/* Marks a method to register and to give to the proxy, see umi_register. The server runs it as it is written.
+ * Options: `false` - the return type is not a proxy type, `fallible` - the proxy method returns Result<T, UmiError>,
+ * a generic method lists the instantiations to register, e.g. #[umi_struct_method(false, <String>, <u32>)]
+ */
 #[proc_macro_attribute]
 pub fn umi_struct_method(args: TokenStream, input: TokenStream) -> TokenStream {
-    let mut func_block = parse_macro_input!(input as syn::Item);
-    let mut func_block_clone = func_block.clone();
-    let func_block_clone_imm = func_block.clone();
-    //assert!(args.is_empty());
-    let mut expr_visitor = ExprVisitor::new();
-    let mut return_type = ReturnTypeOptions::Default;
-    let mut return_type_ident: Option<syn::TypePath> = None;
-    let mut func_ident: Option<syn::Ident> = None;
-    match &func_block_clone_imm {
-        syn::Item::Fn(ref func) => {
-            let output = &func.sig.output;
-            func_ident = Some(func.sig.ident.clone()); // get the function name, it should not reach the None case, unless the function is not named
-            match output {
-                syn::ReturnType::Default => {},
-                syn::ReturnType::Type(_, ref ty) => {
-                    match **ty {
-                        syn::Type::Path(ref tp) => {
-                            return_type = ReturnTypeOptions::Owned;
-                            return_type_ident = Some(tp.clone());
-                        },
-                        syn::Type::Reference(ref r) => {
-                            match *r.elem {
-                                syn::Type::Path(ref tp) => {
-                                    return_type_ident = Some(tp.clone());
-                                },
-                                _ => {
-                                    unimplemented!(); // the reference type might be other options
-                                }
-                            }
-                            match r.mutability {
-                                Some(_) => {
-                                    return_type = ReturnTypeOptions::MutRef;
-                                },
-                                None => {
-                                    return_type = ReturnTypeOptions::Ref;
-                                }
-                            }
-                        },
-                        _ => {
-                            unimplemented!(); // might be other types ?
+    let func = parse_macro_input!(input as syn::ImplItemMethod);
+    parse_macro_input!(args as MethodOptions);
+    func.into_token_stream().into()
+}
+
+/* Marks a method of a proxied enum to register. It matches on self, and gets the arm of the Remote variant,
+ * which invokes the method on the endpoint. Options as for umi_struct_method, a fallible method returns
+ * Result<T, UmiError> on local values and proxies alike
+ */
+#[proc_macro_attribute]
+pub fn umi_enum_method(args: TokenStream, input: TokenStream) -> TokenStream {
+    let MethodOptions { return_lifted, fallible, .. } = parse_macro_input!(args as MethodOptions);
+    let mut func = parse_macro_input!(input as syn::ItemFn);
+    let (return_type, return_type_ident) = match &func.sig.output {
+        syn::ReturnType::Default => (ReturnTypeOptions::Default, None),
+        syn::ReturnType::Type(_, ty) => match **ty {
+            syn::Type::Path(ref tp) => (ReturnTypeOptions::Owned, Some(tp.clone())),
+            syn::Type::Reference(ref r) => match *r.elem {
+                syn::Type::Path(ref tp) if fallible => {
+                    return syn::Error::new_spanned(tp, "umi_enum_method error: a fallible method has to return an owned value").to_compile_error().into();
+                },
+                syn::Type::Path(ref tp) => match r.mutability {
+                    Some(_) => (ReturnTypeOptions::MutRef, Some(tp.clone())),
+                    None => (ReturnTypeOptions::Ref, Some(tp.clone()))
+                },
+                ref elem => return syn::Error::new_spanned(elem, "umi_enum_method error: a returned reference has to be to a named type").to_compile_error().into()
+            },
+            ref ty => return syn::Error::new_spanned(ty, "umi_enum_method error: the return type has to be a named type or a reference to one").to_compile_error().into()
+        }
+    };
+    let op = match return_type {
+        ReturnTypeOptions::Default | ReturnTypeOptions::Owned => quote! { InvokeOp::Owned },
+        ReturnTypeOptions::Ref => quote! { InvokeOp::Ref },
+        ReturnTypeOptions::MutRef => quote! { InvokeOp::MutRef }
+    };
+    let mut args_gen = Vec::new();
+    for input in &func.sig.inputs {
+        match input {
+            syn::FnArg::Receiver(r) => args_gen.push(match r.mutability {
+                Some(_) => quote! { Variable::MutRefRemote(encode(&self).unwrap(), addr.clone(), *id) },
+                None => quote! { Variable::RefRemote(encode(&self).unwrap(), addr.clone(), *id) }
+            }),
+            syn::FnArg::Typed(pat) => match *pat.pat {
+                syn::Pat::Ident(ref ident) => {
+                    let ident = &ident.ident;
+                    args_gen.push(quote! { #ident.to_variable() });
+                },
+                ref pat => return syn::Error::new_spanned(pat, "umi_enum_method error: the arguments have to be named").to_compile_error().into()
+            }
+        }
+    }
+    let method_path = gen_method_path(quote! { Self }, &func.sig);
+    let unwrap = gen_unwrap(fallible);
+    let wrong_return = gen_invalid_return(fallible, "Wrong return value");
+    // the endpoint runs the method as it is generated, a fallible method returns its value wrapped in Ok
+    let result = match gen_remote_match_expr(return_type, return_type_ident, return_lifted, fallible) {
+        Some(match_expr) if fallible => quote! { Ok(#match_expr) },
+        Some(match_expr) => quote! { #match_expr },
+        None if fallible => quote! {
+            match returned {
+                ReturnVar::Owned(s) => decode::<Result<(), UmiError>>(&s)?,
+                _ => {#wrong_return}
+            }
+        },
+        None => quote! {}
+    };
+    let arm: syn::Arm = parse_quote! {
+        Self::Remote(ref addr, ref id, _) => {
+            let msg = Message::Invoke(fn_type_name(&#method_path).to_string(), vec![#(#args_gen),*], #op);
+            let returned = invoke(addr, msg)#unwrap;
+            #result
+        }
+    };
+    let guard = gen_guard_return(&mut func.sig);
+    if fallible {
+        func.sig.output = match &func.sig.output {
+            syn::ReturnType::Default => parse_quote! { -> Result<(), UmiError> },
+            syn::ReturnType::Type(_, ty) => parse_quote! { -> Result<#ty, UmiError> }
+        };
+    }
+    // add the arm to the match expression matching on self, before a wildcard arm can match a proxy,
+    // the values of the other arms are wrapped as the return type
+    for stmt in func.block.stmts.iter_mut() {
+        if let syn::Stmt::Expr(syn::Expr::Match(ref mut m)) = stmt {
+            if let syn::Expr::Path(ref p) = *m.expr {
+                if p.path.is_ident("self") {
+                    for local_arm in m.arms.iter_mut() {
+                        let body = &local_arm.body;
+                        if let Some(guard) = &guard {
+                            local_arm.body = parse_quote! { #guard::Local(#body) };
+                        } else if fallible {
+                            local_arm.body = parse_quote! { Ok(#body) };
                         }
                     }
+                    m.arms.insert(0, arm.clone());
+                }
+            }
+        }
+    }
+    func.into_token_stream().into()
+}
+
+// replaces Self with the implementing type and the generic parameters of the impl block with the registered instantiation,
+// registrations are generated outside of the impl block
+struct Instantiate<'a> {
+    self_ty: &'a syn::Type,
+    params: &'a HashMap<String, syn::GenericArgument>,
+}
+
+impl<'a> VisitMut for Instantiate<'a> {
+    fn visit_type_mut(&mut self, node: &mut syn::Type) {
+        if let syn::Type::Path(ref p) = node {
+            if p.qself.is_none() && p.path.is_ident("Self") {
+                *node = self.self_ty.clone();
+                return;
+            }
+            if let (None, Some(ident)) = (&p.qself, p.path.get_ident()) {
+                if let Some(syn::GenericArgument::Type(ty)) = self.params.get(&ident.to_string()) {
+                    *node = ty.clone();
+                    return;
+                }
+            }
+        }
+        visit_mut::visit_type_mut(self, node);
+    }
+
+    fn visit_lifetime_mut(&mut self, node: &mut syn::Lifetime) {
+        if let Some(syn::GenericArgument::Lifetime(lifetime)) = self.params.get(&node.to_string()) {
+            *node = lifetime.clone();
+        }
+    }
+
+    // the registered function pointer types use elided lifetimes
+    fn visit_type_reference_mut(&mut self, node: &mut syn::TypeReference) {
+        node.lifetime = None;
+        visit_mut::visit_type_reference_mut(self, node);
+    }
+}
+
+fn camel_case(ident: &syn::Ident) -> String {
+    ident.to_string().split('_').map(|part| {
+        let mut chars = part.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => String::new()
+        }
+    }).collect()
+}
+
+// the register! calls of a method annotated with #[umi_init], #[umi_struct_method] or #[umi_enum_method],
+// one for each instantiation listed by a generic method
+fn gen_registration(self_ty: &syn::Type, params: &HashMap<String, syn::GenericArgument>, method: &syn::ImplItemMethod) -> Vec<impl ToTokens> {
+    let annotated = method.attrs.iter().any(|attr| match attr.path.segments.last() {
+        Some(segment) => segment.ident == "umi_init" || segment.ident == "umi_struct_method" || segment.ident == "umi_enum_method",
+        None => false
+    });
+    if !annotated {
+        return Vec::new();
+    }
+    let method_params: Vec<&syn::Ident> = method.sig.generics.params.iter().filter_map(|p| match p {
+        syn::GenericParam::Type(t) => Some(&t.ident),
+        syn::GenericParam::Const(c) => Some(&c.ident),
+        syn::GenericParam::Lifetime(_) => None
+    }).collect();
+    if method_params.is_empty() {
+        return vec![gen_instance_registration(self_ty, params, method, None)];
+    }
+    let instances = match method.attrs.iter().find(|attr| attr.path.segments.last().is_some_and(|segment| segment.ident == "umi_struct_method")) {
+        Some(attr) => method_options(attr).instances,
+        None => Vec::new()
+    };
+    if instances.is_empty() {
+        panic!("umi_register error: the generic method {} has to list the instantiations to register, e.g. #[umi_struct_method(<String>)]", method.sig.ident);
+    }
+    instances.iter().enumerate().map(|(i, instance)| {
+        if instance.args.len() != method_params.len() {
+            panic!("umi_register error: {} does not instantiate {}", instance.to_token_stream(), method.sig.ident);
+        }
+        let mut params = params.clone();
+        for (param, arg) in method_params.iter().zip(instance.args.iter()) {
+            params.insert(param.to_string(), arg.clone());
+        }
+        let mut args = instance.clone();
+        for arg in args.args.iter_mut() {
+            Instantiate { self_ty, params: &params }.visit_generic_argument_mut(arg);
+        }
+        gen_instance_registration(self_ty, &params, method, Some((i, args)))
+    }).collect()
+}
+
+// the register! call of a method, with the arguments of an instantiation of a generic method and its position in the list
+fn gen_instance_registration(self_ty: &syn::Type, params: &HashMap<String, syn::GenericArgument>, method: &syn::ImplItemMethod, instance: Option<(usize, syn::AngleBracketedGenericArguments)>) -> impl ToTokens {
+    let is_init = method.attrs.iter().any(|attr| attr.path.segments.last().is_some_and(|segment| segment.ident == "umi_init"));
+    // umi_enum_method turns the return type T of a fallible method into Result<T, UmiError>
+    let fallible_enum_method = method.attrs.iter()
+        .find(|attr| attr.path.segments.last().is_some_and(|segment| segment.ident == "umi_enum_method"))
+        .is_some_and(|attr| method_options(attr).fallible);
+    let replace = |ty: &syn::Type| {
+        let mut ty = ty.clone();
+        Instantiate { self_ty, params }.visit_type_mut(&mut ty);
+        ty
+    };
+    // the types each argument is decoded as, and how the function takes them
+    let mut plain_tys: Vec<syn::Type> = Vec::new();
+    let mut arg_tys: Vec<syn::Type> = Vec::new();
+    for input in &method.sig.inputs {
+        match input {
+            syn::FnArg::Receiver(r) => {
+                plain_tys.push(self_ty.clone());
+                arg_tys.push(match (&r.reference, &r.mutability) {
+                    (Some(_), Some(_)) => parse_quote! { &mut #self_ty },
+                    (Some(_), None) => parse_quote! { &#self_ty },
+                    (None, _) => self_ty.clone()
+                });
+            },
+            syn::FnArg::Typed(pat) => {
+                let ty = replace(&pat.ty);
+                match ty {
+                    syn::Type::Reference(ref r) => plain_tys.push((*r.elem).clone()),
+                    _ => plain_tys.push(ty.clone())
                 }
+                arg_tys.push(ty);
+            }
+        }
+    }
+    let self_path = match self_ty {
+        syn::Type::Path(p) => turbofish(p.clone()).path,
+        _ => panic!("umi_register error: the implementing type has to be a path")
+    };
+    let method_ident = &method.sig.ident;
+    let type_ident = &self_path.segments.last().unwrap().ident;
+    let (name, method_args) = match instance {
+        Some((i, mut args)) => {
+            args.colon2_token = Some(Default::default());
+            (format_ident!("{}{}{}", type_ident, camel_case(method_ident), i), quote! { #args })
+        },
+        None => (format_ident!("{}{}", type_ident, camel_case(method_ident)), quote! {})
+    };
+    let output_ty: Option<syn::Type> = match &method.sig.output {
+        syn::ReturnType::Type(_, ty) if fallible_enum_method => Some(parse_quote! { Result<#ty, UmiError> }),
+        syn::ReturnType::Default if fallible_enum_method => Some(parse_quote! { Result<(), UmiError> }),
+        syn::ReturnType::Type(_, ty) => Some((**ty).clone()),
+        syn::ReturnType::Default => None
+    };
+    let (output, result) = match &output_ty {
+        Some(ty) => {
+            let ty = replace(ty);
+            match ty {
+                syn::Type::Reference(ref r) => {
+                    let elem = &r.elem;
+                    let op = match r.mutability {
+                        Some(_) => quote! { ResultOp::MutRef },
+                        None => quote! { ResultOp::Ref }
+                    };
+                    (quote! { -> #ty }, quote! { (#elem, #ty, #op) })
+                },
+                // a constructor returning Result<Self, E> stores the value, an error is sent back instead
+                _ if is_init && result_ok_type(&ty).is_some() => {
+                    let value = result_ok_type(&ty).unwrap();
+                    (quote! { -> #ty }, quote! { (#value, #ty, ResultOp::TryInit) })
+                },
+                _ => (quote! { -> #ty }, quote! { (#ty, ResultOp::Owned) })
             }
         },
-        _ => {panic!("umi_method error: Not a function")} // should not reach here
+        None => (quote! {}, quote! { ((), ResultOp::Owned) })
+    };
+    quote! {
+        register!(table, #name, #self_path::#method_ident #method_args, fn(#(#arg_tys),*) #output, #result #(, #plain_tys)* #(, #arg_tys)*);
+    }
+}
+
+// T of a type Result<T, E>
+fn result_ok_type(ty: &syn::Type) -> Option<syn::Type> {
+    let segment = match ty {
+        syn::Type::Path(p) if p.qself.is_none() => p.path.segments.last()?,
+        _ => return None
+    };
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if segment.ident == "Result" && args.args.len() == 2 => match args.args.first() {
+            Some(syn::GenericArgument::Type(ty)) => Some(ty.clone()),
+            _ => None
+        },
+        _ => None
     }
-    let mut return_lifted = true;
-    if !args.is_empty() {
-        if args.to_string() == "false" {
-            return_lifted = false;
+}
+
+// replaces Self in the signature of a proxy method with the proxied type
+struct ReplaceSelf<'a>(&'a syn::Type);
+
+impl<'a> VisitMut for ReplaceSelf<'a> {
+    fn visit_type_mut(&mut self, node: &mut syn::Type) {
+        if let syn::Type::Path(ref p) = node {
+            if p.qself.is_none() && p.path.is_ident("Self") {
+                *node = self.0.clone();
+                return;
+            }
         }
+        visit_mut::visit_type_mut(self, node);
     }
-    let match_expr = gen_remote_match_expr(return_type.clone(), return_type_ident, return_lifted);
-    
-    if match_expr.is_some() { // has return value
-        let match_expr = match_expr.unwrap();
-        match &mut func_block_clone {
-            syn::Item::Fn(ref mut func) => {
-                expr_visitor.visit_item_fn(func);
-                let invariants = expr_visitor.idents;
-                ExprReplace.visit_item_fn_mut(func);
-                let stmts = &func.block.stmts;
-                let func_ident = func_ident.unwrap(); // it is safe to unwrap here
-                // Processing arguments for the function
-                let inputs = &func.sig.inputs;
-                let mut args_gen = Vec::new();
-                let mut is_mut_self = false;
-                for input in inputs {
-                    match input {
-                        syn::FnArg::Receiver(ref r) => { //self
-                            match r.mutability {
-                                Some(_) => {
-                                    let gen = quote! {
-                                        Variable::MutRefRemote(serde_json::to_string(&self).unwrap(), *addr, *id)
-                                    };
-                                    args_gen.push(gen);
-                                    is_mut_self = true; // self is mutable
-                                },
-                                None => {
-                                    let gen = quote! {
-                                        Variable::RefRemote(serde_json::to_string(&self).unwrap(), *addr, *id)
-                                    };
-                                    args_gen.push(gen);
-                                }
-                            }
-                        },
-                        syn::FnArg::Typed(ref pat) => { //other than self
-                            match *pat.pat {
-                                syn::Pat::Ident(ref ident) => {
-                                    let ident = &ident.ident;
-                                    let gen = quote! {
-                                        #ident.to_variable()
-                                    };
-                                    args_gen.push(gen);
-                                },
-                                _ => {// should not reach here
-                                    unimplemented!();
-                                }
-                            }
+}
+
+// the type generated by proxy_me next to the proxied type, e.g. StudentRecordProxy, with a lifetime for the proxies of references
+fn proxy_type(self_ty: &syn::Type, suffix: &str, lifetime: Option<syn::Lifetime>) -> syn::Type {
+    let mut ty = match self_ty {
+        syn::Type::Path(p) => p.clone(),
+        _ => panic!("umi_register error: the implementing type has to be a path")
+    };
+    let segment = ty.path.segments.last_mut().unwrap();
+    segment.ident = format_ident!("{}{}", segment.ident, suffix);
+    if let Some(lifetime) = lifetime {
+        match segment.arguments {
+            syn::PathArguments::AngleBracketed(ref mut args) => args.args.insert(0, syn::GenericArgument::Lifetime(lifetime)),
+            _ => segment.arguments = syn::PathArguments::AngleBracketed(parse_quote! { <#lifetime> })
+        }
+    }
+    syn::Type::Path(ty)
+}
+
+// whether a type names one of the type parameters of an impl block or of a method
+struct MentionsParams<'a> {
+    params: &'a [syn::Ident],
+    found: bool,
+}
+
+impl<'a, 'ast> Visit<'ast> for MentionsParams<'a> {
+    fn visit_path(&mut self, node: &'ast syn::Path) {
+        if node.leading_colon.is_none() && node.segments.first().is_some_and(|segment| self.params.contains(&segment.ident)) {
+            self.found = true;
+        }
+        visit::visit_path(self, node);
+    }
+}
+
+fn mentions_params(ty: &syn::Type, params: &[syn::Ident]) -> bool {
+    let mut visitor = MentionsParams { params, found: false };
+    visitor.visit_type(ty);
+    visitor.found
+}
+
+// a method of the proxy, and of the proxies of references, calling a method annotated with #[umi_struct_method]
+struct ProxyMethod {
+    method: syn::ImplItemMethod, // with the signature of the proxy method
+    is_mut: bool, // takes &mut self
+    local: syn::Expr, // the call on the local value umi_self
+    remote: syn::Expr, // the invocation on the endpoint umi_node holding the resource umi_id
+    args: Vec<syn::Ident>,
+}
+
+fn gen_proxy_method(self_ty: &syn::Type, generics: &syn::Generics, method: &syn::ImplItemMethod) -> Option<ProxyMethod> {
+    let attr = method.attrs.iter().find(|attr| match attr.path.segments.last() {
+        Some(segment) => segment.ident == "umi_struct_method",
+        None => false
+    })?;
+    let MethodOptions { return_lifted, fallible, .. } = method_options(attr);
+    let mut sig = method.sig.clone();
+    ReplaceSelf(self_ty).visit_signature_mut(&mut sig);
+    // the bounds the proxy method needs on the type parameters, which the impl block does not have to write
+    let params: Vec<syn::Ident> = generics.type_params().chain(sig.generics.type_params()).map(|p| p.ident.clone()).collect();
+    let mut bounds: Vec<syn::WherePredicate> = Vec::new();
+    for input in sig.inputs.iter() {
+        match input {
+            syn::FnArg::Receiver(_) if mentions_params(self_ty, &params) => {
+                let proxy = proxy_type(self_ty, "Proxy", None);
+                bounds.push(parse_quote! { #proxy: Serialize });
+            },
+            syn::FnArg::Typed(pat) if mentions_params(&pat.ty, &params) => {
+                bounds.push(match *pat.ty {
+                    syn::Type::Reference(ref r) => {
+                        let elem = &r.elem;
+                        match r.mutability {
+                            Some(_) => parse_quote! { #elem: ToVariableMutL },
+                            None => parse_quote! { #elem: ToVariableRefL }
                         }
-                    }
-                }
-                // invoke op
-                let op;
-                match return_type {
-                    ReturnTypeOptions::Owned | ReturnTypeOptions::Default => {
-                        op = quote! {InvokeOp::Owned};
                     },
-                    ReturnTypeOptions::Ref => {
-                        op = quote! {InvokeOp::Ref};
+                    ref ty => parse_quote! { #ty: ToVariableL }
+                });
+            },
+            _ => {}
+        }
+    }
+    let mut is_mut = false;
+    let mut args = Vec::new();
+    let mut args_gen = Vec::new();
+    for input in sig.inputs.iter_mut() {
+        match input {
+            syn::FnArg::Receiver(r) => {
+                if r.reference.is_none() {
+                    panic!("umi_struct_method error: {} has to take self by reference", sig.ident);
+                }
+                is_mut = r.mutability.is_some();
+                args_gen.push(if is_mut {
+                    quote! { Variable::MutRefRemote(encode(&self).unwrap(), umi_node.clone(), *umi_id) }
+                } else {
+                    quote! { Variable::RefRemote(encode(&self).unwrap(), umi_node.clone(), *umi_id) }
+                });
+            },
+            syn::FnArg::Typed(pat) => {
+                match *pat.pat {
+                    syn::Pat::Ident(ref mut ident) => {
+                        // the proxy method only passes its arguments on
+                        ident.mutability = None;
+                        ident.by_ref = None;
+                        let ident = &ident.ident;
+                        args_gen.push(quote! { #ident.to_variable() });
+                        args.push(ident.clone());
                     },
-                    ReturnTypeOptions::MutRef => {
-                        op = quote! {InvokeOp::MutRef};
+                    _ => panic!("umi_struct_method error: the arguments of {} have to be named", sig.ident)
+                }
+            }
+        }
+    }
+    if sig.inputs.is_empty() || !matches!(sig.inputs[0], syn::FnArg::Receiver(_)) {
+        panic!("umi_struct_method error: {} has to take self by reference", sig.ident);
+    }
+    let method_path = gen_method_path(quote! { <#self_ty> }, &method.sig);
+    let unwrap = gen_unwrap(fallible);
+    let wrong_return = gen_invalid_return(fallible, "Wrong return value");
+    let call = quote! { #method_path(umi_self #(, #args)*) };
+    let (local, op, result) = match sig.output.clone() {
+        syn::ReturnType::Default => {
+            let result = if fallible {
+                quote! {
+                    match invoke(umi_node, msg)? {
+                        ReturnVar::Owned(_) => Ok(()),
+                        _ => {#wrong_return}
                     }
                 }
-                let gen;
-                if is_mut_self {
-                    gen = quote! {
-                        match self {
-                            Self::Local{#(ref mut #invariants),* , ..} => {
-                                #(#stmts)*
-                            },
-                            Self::Remote(ref addr, ref id, is_owner) => {
-                                let msg = Message::Invoke(fn_type_name(&Self::#func_ident).to_string(), 
-                                vec![#(#args_gen),*], #op);
-                                let result_msg = send(addr, msg).unwrap();
-                                //println!("{:?}", result_msg);
-                                let deserialised: Message = serde_json::from_str(&*result_msg).unwrap();
-                                #match_expr
-                            }
-                        }
-                    };
-                } else {
-                    gen = quote! {
-                        match self {
-                            Self::Local{#(ref #invariants),* , ..} => {
-                                #(#stmts)*
-                            },
-                            Self::Remote(ref addr, ref id, is_owner) => {
-                                let msg = Message::Invoke(fn_type_name(&Self::#func_ident).to_string(),
-                                vec![#(#args_gen),*], #op);
-                                let result_msg = send(addr, msg).unwrap();
-                                //println!("{:?}", result_msg);
-                                let deserialised: Message = serde_json::from_str(&*result_msg).unwrap();
-                                #match_expr
-                            }
-                        }
-                    };
+            } else {
+                quote! { invoke(umi_node, msg).unwrap(); }
+            };
+            if fallible {
+                sig.output = parse_quote! { -> Result<(), UmiError> };
+            }
+            let local = if fallible { quote! { Ok(#call) } } else { quote! { #call; } };
+            (local, quote! { InvokeOp::Owned }, result)
+        },
+        syn::ReturnType::Type(_, ty) => match *ty {
+            // a reference is returned as the proxy of the reference, which releases the borrow on the endpoint when dropped
+            syn::Type::Reference(r) => {
+                if fallible {
+                    panic!("umi_struct_method error: a fallible method has to return an owned value");
                 }
-                
-                let expr: syn::Expr = syn::parse(gen.into()).unwrap();
-                match &mut func_block {
-                    syn::Item::Fn(ref mut func) => {
-                        func.block.stmts = vec![syn::Stmt::Expr(expr)];
-                        func_block.into_token_stream().into()
-                    },
-                    _ => {panic!("umi_method error: Not a function")} // should not reach here
+                let elem = &r.elem;
+                if mentions_params(elem, &params) {
+                    bounds.push(parse_quote! { #elem: Proxied });
                 }
-            },
-            _ => {panic!("umi_init error: Not a function")} // should not reach here
-        }   
-    } else { // no return value
-        match &mut func_block_clone {
-            syn::Item::Fn(ref mut func) => {
-                expr_visitor.visit_item_fn(func);
-                let invariants = expr_visitor.idents;
-                ExprReplace.visit_item_fn_mut(func);
-                let stmts = &func.block.stmts;
-                // Processing arguments for the function
-                let inputs = &func.sig.inputs;
-                let mut args_gen = Vec::new();
-                for input in inputs {
-                    match input {
-                        syn::FnArg::Receiver(ref r) => {
-                            match r.mutability {
-                                Some(_) => {
-                                    let gen = quote! {
-                                        Variable::MutRefRemote(serde_json::to_string(&self).unwrap(), *addr, *id)
-                                    };
-                                    args_gen.push(gen);
-                                },
-                                None => {
-                                    let gen = quote! {
-                                        Variable::RefRemote(serde_json::to_string(&self).unwrap(), *addr, *id)
-                                    };
-                                    args_gen.push(gen);
-                                }
-                            }
+                let lifetime = match &r.lifetime {
+                    Some(l) => quote! { #l },
+                    None => quote! { '_ }
+                };
+                let (view, remote_view, op, owned) = match r.mutability {
+                    Some(_) => (quote! { Mut }, quote! { remote_mut }, quote! { InvokeOp::MutRef }, quote! { ReturnVar::MutRefOwned }),
+                    None => (quote! { Ref }, quote! { remote_ref }, quote! { InvokeOp::Ref }, quote! { ReturnVar::RefOwned })
+                };
+                sig.output = parse_quote! { -> <#elem as Proxied>::#view<#lifetime> };
+                let result = quote! {
+                    match invoke(umi_node, msg).unwrap() {
+                        #owned(node, id) => {
+                            // the entry lives on the node of self, reached at the addresses of self
+                            let node = node.reached_at(&umi_node.addrs);
+                            let remote = <<#elem as Proxied>::Proxy as Proxy>::construct_remote(node.clone(), id, Arc::new(AtomicBool::new(false)));
+                            <#elem as Proxied>::#remote_view(remote, Some(RemoteBorrow::new(node, id)))
                         },
-                        syn::FnArg::Typed(ref pat) => {
-                            match *pat.pat {
-                                syn::Pat::Ident(ref ident) => {
-                                    let ident = &ident.ident;
-                                    let gen = quote! {
-                                        #ident.to_variable()
-                                    };
-                                    args_gen.push(gen);
-                                },
-                                _ => {// should not reach here
-                                    unimplemented!();
-                                }
-                            }
-                        }
+                        _ => {#wrong_return}
+                    }
+                };
+                (quote! { #call.into() }, op, result)
+            },
+            ty => {
+                if mentions_params(&ty, &params) {
+                    bounds.push(parse_quote! { #ty: serde::de::DeserializeOwned });
+                    if return_lifted {
+                        bounds.push(parse_quote! { #ty: Lift });
                     }
                 }
-                let gen = quote! {
-                    match self {
-                        Self::Local{#(#invariants),* , ..} => {
-                            #(#stmts)*
+                let lift = if return_lifted {
+                    quote! { Lift::lift(result) }
+                } else {
+                    quote! { result }
+                };
+                let result = quote! {
+                    match invoke(umi_node, msg)#unwrap {
+                        ReturnVar::Owned(s) => {
+                            let result: #ty = decode(&s)#unwrap;
+                            #lift
                         },
-                        Self::Remote(ref addr, ref id, is_owner) => {
-                            //println!("here invoking");
-                            let msg = Message::Invoke(fn_type_name(&Self::#func_ident).to_string(), 
-                            vec![#(#args_gen),*], InvokeOp::Owned);
-                            send(addr, msg).unwrap();
-                        }
+                        _ => {#wrong_return}
                     }
                 };
-                let expr: syn::Expr = syn::parse(gen.into()).unwrap();
-                match &mut func_block {
-                    syn::Item::Fn(ref mut func) => {
-                        func.block.stmts = vec![syn::Stmt::Expr(expr)];
-                        func_block.into_token_stream().into()
-                    },
-                    _ => {panic!("umi_method error: Not a function")} // should not reach here
+                if fallible {
+                    sig.output = parse_quote! { -> Result<#ty, UmiError> };
+                    (quote! { Ok(#call) }, quote! { InvokeOp::Owned }, quote! { Ok(#result) })
+                } else {
+                    (quote! { #call }, quote! { InvokeOp::Owned }, result)
                 }
-            },
-            _ => {panic!("umi_init error: Not a function")} // should not reach here
+            }
+        }
+    };
+    let remote = parse_quote! {
+        {
+            let msg = Message::Invoke(fn_type_name(&#method_path).to_string(), vec![#(#args_gen),*], #op);
+            #result
         }
+    };
+    let local = parse_quote! { { #local } };
+    if !bounds.is_empty() {
+        sig.generics.make_where_clause().predicates.extend(bounds);
     }
+    // the proxy method keeps the documentation of the method
+    let attrs = method.attrs.iter().filter(|attr| attr.path.is_ident("doc")).cloned().collect();
+    let method = syn::ImplItemMethod {
+        attrs,
+        vis: method.vis.clone(),
+        defaultness: None,
+        sig,
+        block: parse_quote! { {} },
+    };
+    Some(ProxyMethod { method, is_mut, local, remote, args })
 }
 
-#[proc_macro_attribute]
-pub fn umi_enum_method(args: TokenStream, input: TokenStream) -> TokenStream {
-    let mut func_block = parse_macro_input!(input as syn::Item);
-    let mut func_block_clone = func_block.clone();
-    let func_block_clone_imm = func_block.clone();
-    assert!(args.is_empty());
-    let mut expr_visitor = ExprVisitor::new();
-    let mut return_type = ReturnTypeOptions::Default;
-    let mut return_type_ident: Option<syn::TypePath> = None;
-    let mut func_ident: Option<syn::Ident> = None;
-    match &func_block_clone_imm {
-        syn::Item::Fn(ref func) => {
-            let output = &func.sig.output;
-            func_ident = Some(func.sig.ident.clone()); // get the function name, it should not reach the None case, unless the function is not named
-            match output {
-                syn::ReturnType::Default => {},
-                syn::ReturnType::Type(_, ref ty) => {
-                    match **ty {
-                        syn::Type::Path(ref tp) => {
-                            return_type = ReturnTypeOptions::Owned;
-                            // return_type_ident = Some(tp.path.segments[0].ident.clone());
-                            return_type_ident = Some(tp.clone());
-                        },
-                        syn::Type::Reference(ref r) => {
-                            match *r.elem {
-                                syn::Type::Path(ref tp) => {
-                                    //return_type_ident = Some(tp.path.segments[0].ident.clone());
-                                    return_type_ident = Some(tp.clone());
-                                },
-                                _ => {
-                                    unimplemented!(); // the reference type might be other options
-                                }
-                            }
-                            match r.mutability {
-                                Some(_) => {
-                                    return_type = ReturnTypeOptions::MutRef;
-                                },
-                                None => {
-                                    return_type = ReturnTypeOptions::Ref;
-                                }
-                            }
-                        },
-                        _ => {
-                            unimplemented!(); // might be other types ?
-                        }
-                    }
+/* The methods of the proxy and of the proxies of references generated by proxy_me for the type of the impl block,
+ * the proxy of a reference has the methods that its reference can call
+ */
+fn gen_proxy_impls(item_impl: &syn::ItemImpl) -> Option<impl ToTokens> {
+    let self_ty = &item_impl.self_ty;
+    let methods: Vec<ProxyMethod> = item_impl.items.iter().filter_map(|item| match item {
+        syn::ImplItem::Method(method) => gen_proxy_method(self_ty, &item_impl.generics, method),
+        _ => None
+    }).collect();
+    if methods.is_empty() {
+        return None;
+    }
+    let mut proxy_methods = Vec::new();
+    let mut ref_methods = Vec::new();
+    let mut mut_methods = Vec::new();
+    for ProxyMethod { method, is_mut, local, remote, args } in methods {
+        let binding = if is_mut { quote! { ref mut umi_self } } else { quote! { ref umi_self } };
+        let mut proxy_method = method.clone();
+        proxy_method.block = parse_quote! {
+            {
+                match self {
+                    Self::Local(#binding) => #local,
+                    Self::Remote(ref umi_node, ref umi_id, _) => #remote
                 }
             }
-        },
-        _ => {panic!("umi_method error: Not a function")} // should not reach here
+        };
+        proxy_methods.push(proxy_method);
+        let ident = &method.sig.ident;
+        let params = gen_method_params(&method.sig);
+        let mut view_method = method.clone();
+        view_method.block = parse_quote! {
+            {
+                match self {
+                    Self::Local(umi_self) => #local,
+                    Self::Remote(umi_proxy, _) => umi_proxy.#ident #params(#(#args),*)
+                }
+            }
+        };
+        if !is_mut {
+            ref_methods.push(view_method.clone());
+        }
+        mut_methods.push(view_method);
     }
-    let mut return_lifted = true;
-    if !args.is_empty() {
-        if args.to_string() == "false" {
-            return_lifted = false;
+    let (impl_generics, _, where_clause) = item_impl.generics.split_for_impl();
+    let views = view_generics(&item_impl.generics);
+    let (view_impl_generics, _, _) = views.split_for_impl();
+    let lifetime: syn::Lifetime = parse_quote! { 'umi };
+    let proxy = proxy_type(self_ty, "Proxy", None);
+    let proxy_ref = proxy_type(self_ty, "Ref", Some(lifetime.clone()));
+    let proxy_mut = proxy_type(self_ty, "Mut", Some(lifetime));
+    Some(quote! {
+        impl #impl_generics #proxy #where_clause {
+            #(#proxy_methods)*
+        }
+
+        impl #view_impl_generics #proxy_ref #where_clause {
+            #(#ref_methods)*
         }
+
+        impl #view_impl_generics #proxy_mut #where_clause {
+            #(#mut_methods)*
+        }
+    })
+}
+
+// maps the generic parameters of the impl block to the arguments of an instantiation of its type
+fn instantiation_params(self_ty: &syn::Type, instance: &syn::Type) -> HashMap<String, syn::GenericArgument> {
+    let mut params = HashMap::new();
+    let args = |ty: &syn::Type| match ty {
+        syn::Type::Path(p) => match &p.path.segments.last().unwrap().arguments {
+            syn::PathArguments::AngleBracketed(a) => a.args.iter().cloned().collect(),
+            _ => Vec::new()
+        },
+        _ => panic!("umi_register error: the implementing type has to be a path")
+    };
+    let (generic, concrete) = (args(self_ty), args(instance));
+    if generic.len() != concrete.len() {
+        panic!("umi_register error: {} does not instantiate {}", instance.to_token_stream(), self_ty.to_token_stream());
     }
-    let match_expr = gen_remote_match_expr(return_type.clone(), return_type_ident, return_lifted);
-
-    if match_expr.is_some() { // has return value
-        let match_expr = match_expr.unwrap();
-        match &mut func_block_clone {
-            syn::Item::Fn(ref mut func) => {
-                let func_ident = func_ident.unwrap(); // it is safe to unwrap here
-                // Processing arguments for the function
-                let inputs = &func.sig.inputs;
-                let mut args_gen = Vec::new();
-                let mut is_mut_self = false;
-                for input in inputs {
-                    match input {
-                        syn::FnArg::Receiver(ref r) => { //self
-                            match r.mutability {
-                                Some(_) => {
-                                    let gen = quote! {
-                                        Variable::MutRefRemote(serde_json::to_string(&self).unwrap(), *addr, *id)
-                                    };
-                                    args_gen.push(gen);
-                                    is_mut_self = true; // self is mutable
-                                },
-                                None => {
-                                    let gen = quote! {
-                                        Variable::RefRemote(serde_json::to_string(&self).unwrap(), *addr, *id)
-                                    };
-                                    args_gen.push(gen);
-                                }
-                            }
-                        },
-                        syn::FnArg::Typed(ref pat) => { //other than self
-                            match *pat.pat {
-                                syn::Pat::Ident(ref ident) => {
-                                    let ident = &ident.ident;
-                                    let gen = quote! {
-                                        #ident.to_variable()
-                                    };
-                                    args_gen.push(gen);
-                                },
-                                _ => {// should not reach here
-                                    unimplemented!();
-                                }
-                            }
-                        }
-                    }
-                }
-                // invoke op
-                let op;
-                match return_type {
-                    ReturnTypeOptions::Owned | ReturnTypeOptions::Default => {
-                        op = quote! {InvokeOp::Owned};
-                    },
-                    ReturnTypeOptions::Ref => {
-                        op = quote! {InvokeOp::Ref};
-                    },
-                    ReturnTypeOptions::MutRef => {
-                        op = quote! {InvokeOp::MutRef};
-                    }
-                }
-                let gen;
-                if is_mut_self {
-                    gen = quote! {
-                        Self::Remote(ref addr, ref id, is_owner) => {
-                            let msg = Message::Invoke(fn_type_name(&Self::#func_ident).to_string(), 
-                            vec![#(#args_gen),*], #op);
-                            let result_msg = send(addr, msg).unwrap();
-                            //println!("{:?}", result_msg);
-                            let deserialised: Message = serde_json::from_str(&*result_msg).unwrap();
-                            #match_expr
-                        }
-                    };
-                } else {
-                    gen = quote! {
-                        Self::Remote(ref addr, ref id, is_owner) => {
-                            let msg = Message::Invoke(fn_type_name(&Self::#func_ident).to_string(),
-                            vec![#(#args_gen),*], #op);
-                            let result_msg = send(addr, msg).unwrap();
-                            //println!("{:?}", result_msg);
-                            let deserialised: Message = serde_json::from_str(&*result_msg).unwrap();
-                            #match_expr
-                        }
-                    };
-                }
-                
-                let arm: syn::Arm = syn::parse(gen.into()).unwrap();
-                match &mut func_block {
-                    syn::Item::Fn(ref mut func) => {
-                        // add a match arm to the match expression matching on self in the stmts
-                        for stmt in func.block.stmts.iter_mut() {
-                            match stmt {
-                                syn::Stmt::Expr(ref mut expr) => {
-                                    match expr {
-                                        syn::Expr::Match(ref mut m) => {
-                                            match *m.expr {
-                                                syn::Expr::Path(ref mut p) => {
-                                                    if p.path.is_ident("self") {
-                                                        m.arms.push(arm.clone());
-                                                    } // only add the arm if the match expression is self
-                                                },
-                                                _ => {} // do nothing
-                                            }
-                                            
-                                        },
-                                        _ => {} // do nothing
-                                    }
-                                },
-                                _ => {} // do nothing
-                            }
-                        }
-                        func_block.into_token_stream().into()
-                    },
-                    _ => {panic!("umi_method error: Not a function")} // should not reach here
-                }
+    for (param, arg) in generic.into_iter().zip(concrete) {
+        match param {
+            syn::GenericArgument::Type(syn::Type::Path(p)) if p.path.get_ident().is_some() => {
+                params.insert(p.path.get_ident().unwrap().to_string(), arg);
             },
-            _ => {panic!("umi_init error: Not a function")} // should not reach here
-        }   
-    } else { // no return value
-        match &mut func_block_clone {
-            syn::Item::Fn(ref mut func) => {
-                expr_visitor.visit_item_fn(func);
-                ExprReplace.visit_item_fn_mut(func);
-                // Processing arguments for the function
-                let inputs = &func.sig.inputs;
-                let mut args_gen = Vec::new();
-                for input in inputs {
-                    match input {
-                        syn::FnArg::Receiver(ref r) => {
-                            match r.mutability {
-                                Some(_) => {
-                                    let gen = quote! {
-                                        Variable::MutRefRemote(serde_json::to_string(&self).unwrap(), *addr, *id)
-                                    };
-                                    args_gen.push(gen);
-                                },
-                                None => {
-                                    let gen = quote! {
-                                        Variable::RefRemote(serde_json::to_string(&self).unwrap(), *addr, *id)
-                                    };
-                                    args_gen.push(gen);
-                                }
-                            }
-                        },
-                        syn::FnArg::Typed(ref pat) => {
-                            match *pat.pat {
-                                syn::Pat::Ident(ref ident) => {
-                                    let ident = &ident.ident;
-                                    let gen = quote! {
-                                        #ident.to_variable()
-                                    };
-                                    args_gen.push(gen);
-                                },
-                                _ => {// should not reach here
-                                    unimplemented!();
-                                }
-                            }
-                        }
-                    }
-                }
-                let gen = quote! {
-                    Self::Remote(ref addr, ref id, is_owner) => {
-                        Message::Invoke(fn_type_name(&Self::#func_ident).to_string(), 
-                        vec![#(#args_gen),*], InvokeOp::Owned);
-                    }
-                };
-                
-                let arm: syn::Arm = syn::parse(gen.into()).unwrap();
-                match &mut func_block {
-                    syn::Item::Fn(ref mut func) => {
-                        // add a match arm to the match expression matching on self in the stmts
-                        for stmt in func.block.stmts.iter_mut() {
-                            match stmt {
-                                syn::Stmt::Expr(ref mut expr) => {
-                                    match expr {
-                                        syn::Expr::Match(ref mut m) => {
-                                            match *m.expr {
-                                                syn::Expr::Path(ref mut p) => {
-                                                    if p.path.is_ident("self") {
-                                                        m.arms.push(arm.clone());
-                                                    } // only add the arm if the match expression is self
-                                                },
-                                                _ => {} // do nothing
-                                            }
-                                            
-                                        },
-                                        _ => {} // do nothing
-                                    }
-                                },
-                                _ => {} // do nothing
-                            }
-                        }
-                        func_block.into_token_stream().into()
-                    },
-                    _ => {panic!("umi_method error: Not a function")} // should not reach here
-                }
+            syn::GenericArgument::Lifetime(l) => {
+                params.insert(l.to_string(), arg);
             },
-            _ => {panic!("umi_init error: Not a function")} // should not reach here
+            _ => {} // fixed by the impl block
+        }
+    }
+    params
+}
+
+/* Implements umi::registry::Registrable for the type of the impl block, registering its annotated methods,
+ * so that a server fills its RegistryTable with register_all::<T>(&mut table).
+ * It goes on the impl block, whose methods still carry their own attributes, and needs setup_registry!()
+ * The proxy generated by proxy_me gets the methods annotated with #[umi_struct_method], which call the method
+ * on a local value and invoke it on the endpoint otherwise.
+ * The impl block of a generic type lists the instantiations to register, e.g. #[umi_register(Cache<String, u32>)],
+ * each of them registered by its own register_all::<Cache<String, u32>>
+ */
+#[proc_macro_attribute]
+pub fn umi_register(args: TokenStream, input: TokenStream) -> TokenStream {
+    let item_impl = parse_macro_input!(input as syn::ItemImpl);
+    let instances = parse_macro_input!(args with syn::punctuated::Punctuated::<syn::Type, syn::Token![,]>::parse_terminated);
+    let self_ty = &item_impl.self_ty;
+    let instances: Vec<syn::Type> = if instances.is_empty() {
+        if item_impl.generics.type_params().next().is_some() {
+            panic!("umi_register error: a generic impl block has to list the instantiations to register");
         }
+        vec![(**self_ty).clone()]
+    } else {
+        instances.into_iter().collect()
+    };
+    let mut impls = Vec::new();
+    for instance in &instances {
+        let params = instantiation_params(self_ty, instance);
+        let mut registrations = Vec::new();
+        for item in &item_impl.items {
+            if let syn::ImplItem::Method(method) = item {
+                registrations.extend(gen_registration(instance, &params, method));
+            }
+        }
+        impls.push(quote! {
+            impl Registrable for #instance {
+                fn register_all(table: &mut RegistryTable) {
+                    #(#registrations)*
+                }
+            }
+        });
     }
+    let proxy_impls = gen_proxy_impls(&item_impl);
+    let gen = quote! {
+        #item_impl
+
+        #(#impls)*
+
+        #proxy_impls
+    };
+    gen.into()
+}
+
+// the input of gen_call!, see umi::register!
+struct GenCallInput {
+    krate: syn::Ident,
+    op: syn::Ident,
+    name: syn::Ident,
+    res_ty_plain: syn::Type,
+    res_ty: syn::Type,
+    args_ty_plain: Vec<syn::Type>,
+    args_ty: Vec<syn::Type>,
+}
+
+impl syn::parse::Parse for GenCallInput {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        // $crate is not a keyword for syn
+        let krate = input.parse()?;
+        input.parse::<syn::Token![,]>()?;
+        let op: syn::Ident = input.parse()?;
+        input.parse::<syn::Token![,]>()?;
+        let name = input.parse()?;
+        input.parse::<syn::Token![,]>()?;
+        let res_ty_plain = input.parse()?;
+        input.parse::<syn::Token![,]>()?;
+        let res_ty = input.parse()?;
+        let mut args: Vec<syn::Type> = Vec::new();
+        while !input.is_empty() {
+            input.parse::<syn::Token![,]>()?;
+            args.push(input.parse()?);
+        }
+        if !args.len().is_multiple_of(2) {
+            return Err(syn::Error::new(op.span(), "register! expects as many argument ownerships as argument types"));
+        }
+        let args_ty = args.split_off(args.len() / 2);
+        Ok(GenCallInput { krate, op, name, res_ty_plain, res_ty, args_ty_plain: args, args_ty })
+    }
+}
+
+/* Generates the GenCall implementation of a registered function with any number of arguments, used by umi::register! */
+#[doc(hidden)]
+#[proc_macro]
+pub fn gen_call(input: TokenStream) -> TokenStream {
+    let GenCallInput { krate, op, name, res_ty_plain, res_ty, args_ty_plain, args_ty } = parse_macro_input!(input as GenCallInput);
+    let args: Vec<syn::Ident> = (1..=args_ty.len()).map(|i| format_ident!("arg{}", i)).collect();
+    let wrapped: Vec<syn::Ident> = (1..=args_ty.len()).map(|i| format_ident!("w{}", i)).collect();
+    // the last argument is popped first
+    let popped = args.iter().rev();
+    let args_name = if args.is_empty() { quote! { args } } else { quote! { mut args } };
+    let count = args.len();
+    let ret = match op.to_string().as_str() {
+        "Ref" => quote! {
+            let result: #res_ty = (self.ptr)(#(#wrapped.extract()?),*);
+            let serialised = result.tagged_bytes();
+            let ptr: *const #res_ty_plain = #krate::registry::AsConstPtr::<#res_ty_plain>::as_const_ptr(&result);
+            let boxed: Box<dyn Any + Send + Sync> = Box::new(ConstPtr(ptr));
+        },
+        "MutRef" => quote! {
+            let mut result: #res_ty = (self.ptr)(#(#wrapped.extract()?),*);
+            let serialised = result.tagged_bytes();
+            let ptr: *mut #res_ty_plain = #krate::registry::AsMutPtr::<#res_ty_plain>::as_mut_ptr(&mut result);
+            let boxed: Box<dyn Any + Send + Sync> = Box::new(MutPtr(ptr));
+        },
+        "Owned" => quote! {
+            let result: #res_ty = (self.ptr)(#(#wrapped.extract()?),*);
+            let serialised = result.tagged_bytes();
+            let boxed: Box<dyn Any + Send + Sync> = Box::new(result);
+        },
+        "TryInit" => quote! {
+            let result: #res_ty = (self.ptr)(#(#wrapped.extract()?),*);
+            let result: #res_ty_plain = result.map_err(|e| (#krate::message_serialisation::ErrorCode::InitFailed, format!("{:?}", e)))?;
+            let serialised = result.tagged_bytes();
+            let boxed: Box<dyn Any + Send + Sync> = Box::new(result);
+        },
+        _ => return syn::Error::new(op.span(), "expected Ref, MutRef, Owned or TryInit").to_compile_error().into()
+    };
+    let gen = quote! {
+        #[derive(Clone)]
+        pub struct #name {
+            ptr: fn(#(#args_ty),*) -> #res_ty,
+        }
+
+        impl GenCall for #name {
+            fn call(&self, #args_name: Vec<Argument>) -> #krate::registry::CallResult {
+                #krate::registry::expect_args(&args, #count)?;
+                #(let mut #popped = #krate::registry::pop_arg(&mut args)?;)*
+                #(let #wrapped = #args.get_arg::<#args_ty_plain>()?;)*
+                #ret
+                Ok((serialised, boxed))
+            }
+        }
+    };
+    gen.into()
 }
 
 // imports
@@ -1074,6 +1630,9 @@ pub fn setup_packages(_item: TokenStream) -> TokenStream {
     "use std::sync::atomic::*;
     use std::sync::*;
     use umi::utils::*;
+    use umi::codec::*;
+    use umi::error::*;
+    use umi::lease::*;
     use umi::message_serialisation::*;".parse().unwrap()
 }
 